@@ -0,0 +1,383 @@
+use crate::error::{Error, Result};
+use crate::normalize;
+use serde::Serialize;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// What happens to extracted lines that read backwards, as text stored in
+/// visual order or words the model returned mirrored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BidiMode {
+    /// No check.
+    Off,
+    /// Report the pages with reversed lines in the job summary.
+    #[default]
+    Report,
+    /// Also put reversed lines back into logical order.
+    Repair,
+}
+
+impl FromStr for BidiMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "off" => Ok(Self::Off),
+            "report" => Ok(Self::Report),
+            "repair" => Ok(Self::Repair),
+            other => Err(Error::InvalidInput(format!(
+                "unknown bidi mode '{other}' (expected off, report or repair)"
+            ))),
+        }
+    }
+}
+
+/// The lines of a page that look reversed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReversedLines {
+    pub page: usize,
+    /// Line numbers within the page's text, starting at 1.
+    pub lines: Vec<usize>,
+    pub repaired: bool,
+}
+
+/// Frequent words whose reversal is not a word as well, separated by
+/// spaces. Alef variants are folded before lookup, see [`fold`].
+const COMMON_WORDS: &str = "في من على الى عن مع ان او ثم لا لم لن ما هذا هذه ذلك تلك التي الذي \
+    الذين كان كانت قد بعد قبل بين عند حتى اذا هو هي هم نحن انا انت وهو وقد ولا وفي ومن فيه فيها \
+    منه منها عليه عليها لها به بها الله قال يكون غير اي ايضا حيث كما لقد ليس عام يوم بن ابو كتاب";
+
+fn is_common(word: &str) -> bool {
+    COMMON_WORDS.split_whitespace().any(|common| common == word)
+}
+
+/// A reversed line needs at least this many words that read backwards.
+const MIN_REVERSED_WORDS: usize = 2;
+
+/// Pairs swapped when a line is reversed. `<` and `>` are left out, as
+/// they are far more often comparisons or markup than brackets.
+const MIRRORED: [(char, char); 4] = [('(', ')'), ('[', ']'), ('{', '}'), ('«', '»')];
+
+/// Sizes of the groups of Arabic Presentation Forms-B from U+FE80, one
+/// letter each: isolated and final forms, followed by initial and medial
+/// forms for letters that join on both sides.
+const FORM_GROUPS: [u32; 40] = [
+    1, 2, 2, 2, 2, 4, 2, 4, 2, 4, 4, 4, 4, 4, 2, 2, 2, 2, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 2, 2, 4, 2, 2, 2, 2,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JoiningForm {
+    Isolated,
+    Final,
+    Initial,
+    Medial,
+}
+
+fn joining_form(c: char) -> Option<JoiningForm> {
+    let code = c as u32;
+    let mut start = 0xFE80;
+    for size in FORM_GROUPS {
+        if (start..start + size).contains(&code) {
+            return Some(match code - start {
+                0 => JoiningForm::Isolated,
+                1 => JoiningForm::Final,
+                2 => JoiningForm::Initial,
+                _ => JoiningForm::Medial,
+            });
+        }
+        start += size;
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reading {
+    Forward,
+    Backward,
+}
+
+fn is_arabic(c: char) -> bool {
+    matches!(c, '\u{0600}'..='\u{06FF}' | '\u{FB50}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+/// Letters of `word` without diacritics and tatweel, with presentation
+/// forms and alef variants folded.
+fn fold(word: &str) -> String {
+    word.chars()
+        .filter(|&c| !normalize::is_diacritic(c) && c != '\u{0640}')
+        .flat_map(|c| c.to_string().nfkc().collect::<Vec<_>>())
+        .map(|c| match c {
+            'أ' | 'إ' | 'آ' | 'ٱ' => 'ا',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Which way an Arabic word reads, if it can be told.
+fn word_reading(word: &str) -> Option<Reading> {
+    // Presentation forms give away where the word starts and ends
+    let forms: Vec<JoiningForm> = word.chars().filter_map(joining_form).collect();
+    if forms.len() >= 2 {
+        let (first, last) = (forms[0], forms[forms.len() - 1]);
+        if matches!(first, JoiningForm::Final | JoiningForm::Medial)
+            || matches!(last, JoiningForm::Initial | JoiningForm::Medial)
+        {
+            return Some(Reading::Backward);
+        }
+        if first == JoiningForm::Initial || last == JoiningForm::Final {
+            return Some(Reading::Forward);
+        }
+    }
+
+    let letters = fold(word);
+    let reversed: String = letters.chars().rev().collect();
+    let known = is_common(&letters);
+    let known_reversed = is_common(&reversed);
+    let count = letters.chars().count();
+    if known != known_reversed {
+        return Some(if known {
+            Reading::Forward
+        } else {
+            Reading::Backward
+        });
+    }
+    // Taa marbuta and alef maqsura only end words, and most nouns start
+    // with the article
+    if letters.starts_with(['ة', 'ى']) || (count >= 6 && letters.ends_with("لا")) {
+        return Some(Reading::Backward);
+    }
+    if (count >= 2 && letters.ends_with(['ة', 'ى'])) || (count >= 4 && letters.starts_with("ال"))
+    {
+        return Some(Reading::Forward);
+    }
+    None
+}
+
+/// How many words of a line read forward and how many backward.
+fn score(line: &str) -> (usize, usize) {
+    let mut forward = 0;
+    let mut backward = 0;
+    for word in line
+        .split(|c: char| !is_arabic(c))
+        .filter(|w| !w.is_empty())
+    {
+        match word_reading(word) {
+            Some(Reading::Forward) => forward += 1,
+            Some(Reading::Backward) => backward += 1,
+            None => {}
+        }
+    }
+    (forward, backward)
+}
+
+fn looks_reversed(line: &str) -> bool {
+    let (forward, backward) = score(line);
+    backward >= MIN_REVERSED_WORDS && backward > forward
+}
+
+/// Whether the brackets of `line` only balance once each is swapped for
+/// its mirror image, as in `)كذا(`.
+fn has_mirrored_brackets(line: &str) -> bool {
+    let balanced = |line: &str| {
+        MIRRORED.iter().all(|&(open, close)| {
+            let mut depth = 0i32;
+            for c in line.chars() {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    depth -= 1;
+                    if depth < 0 {
+                        return false;
+                    }
+                }
+            }
+            depth == 0
+        })
+    };
+    let has_brackets = line.chars().any(|c| {
+        MIRRORED
+            .iter()
+            .any(|&(open, close)| c == open || c == close)
+    });
+    has_brackets && !balanced(line) && balanced(&mirror_brackets(line))
+}
+
+fn mirror_brackets(line: &str) -> String {
+    line.chars()
+        .map(|c| {
+            MIRRORED
+                .iter()
+                .find_map(|&(open, close)| {
+                    if c == open {
+                        Some(close)
+                    } else if c == close {
+                        Some(open)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(c)
+        })
+        .collect()
+}
+
+/// Splits `text` into characters with their diacritics.
+fn clusters(text: &str) -> Vec<&str> {
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    for (index, c) in text.char_indices().skip(1) {
+        if !normalize::is_diacritic(c) {
+            clusters.push(&text[start..index]);
+            start = index;
+        }
+    }
+    if !text.is_empty() {
+        clusters.push(&text[start..]);
+    }
+    clusters
+}
+
+/// A line stored in visual order: everything reversed, except runs of
+/// digits and Latin text, which were already stored left to right.
+fn reverse_line(line: &str) -> String {
+    let mut reversed = clusters(line);
+    reversed.reverse();
+
+    let mut output = String::with_capacity(line.len());
+    let mut run: Vec<&str> = Vec::new();
+    for cluster in reversed {
+        if cluster
+            .chars()
+            .any(|c| c.is_alphanumeric() && !is_arabic(c))
+        {
+            run.push(cluster);
+            continue;
+        }
+        output.extend(run.drain(..).rev());
+        output.push_str(cluster);
+    }
+    output.extend(run.drain(..).rev());
+    mirror_brackets(&output)
+}
+
+/// A line whose words are mirrored but in the right order.
+fn reverse_words(line: &str) -> String {
+    let mut output = String::with_capacity(line.len());
+    let mut word = String::new();
+    for c in line.chars() {
+        if is_arabic(c) {
+            word.push(c);
+        } else {
+            output.extend(clusters(&word).into_iter().rev());
+            word.clear();
+            output.push(c);
+        }
+    }
+    output.extend(clusters(&word).into_iter().rev());
+    output
+}
+
+/// Line numbers (from 1) of the lines in `text` that look reversed or
+/// have mirrored brackets.
+pub fn reversed_lines(text: &str) -> Vec<usize> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| looks_reversed(line) || has_mirrored_brackets(line))
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+/// Puts the lines of `text` that look reversed back into logical order,
+/// and swaps mirrored brackets.
+pub fn repair(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            if looks_reversed(line) {
+                repair_line(line)
+            } else if has_mirrored_brackets(line) {
+                mirror_brackets(line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reverses `line` as a whole or word by word, whichever reads better.
+fn repair_line(line: &str) -> String {
+    let gain = |line: &str| {
+        let (forward, backward) = score(line);
+        forward as isize - backward as isize
+    };
+    let whole = reverse_line(line);
+    let words = reverse_words(line);
+    // Visual order shows in presentation forms, which only text layers
+    // use, and in punctuation or numbers ending up at the start
+    let visual = line.chars().any(|c| joining_form(c).is_some())
+        || line
+            .trim_start()
+            .chars()
+            .next()
+            .is_some_and(|c| !is_arabic(c));
+    let (best_gain, best) = match (gain(&whole), gain(&words)) {
+        (whole_gain, words_gain)
+            if whole_gain > words_gain || (whole_gain == words_gain && visual) =>
+        {
+            (whole_gain, whole)
+        }
+        (_, words_gain) => (words_gain, words),
+    };
+    if best_gain > gain(line) {
+        best
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visual_order_lines_are_detected_and_repaired() {
+        let logical = "ذهب الولد إلى المدرسة في الصباح (2024)";
+        let visual = "(2024) حابصلا يف ةسردملا ىلإ دلولا بهذ";
+        let text = format!("{logical}\n{visual}");
+
+        assert_eq!(reversed_lines(&text), [2]);
+        assert_eq!(repair(&text), format!("{logical}\n{logical}"));
+    }
+
+    #[test]
+    fn test_mirrored_words_are_reversed_in_place() {
+        let text = "بهذ دلولا ىلإ ةسردملا";
+        assert_eq!(reversed_lines(text), [1]);
+        assert_eq!(repair(text), "ذهب الولد إلى المدرسة");
+    }
+
+    #[test]
+    fn test_presentation_forms_show_the_reading_direction() {
+        // "سلام عليكم" in presentation forms, stored backwards
+        let logical = "\u{FEB3}\u{FEFC}\u{FEE1} \u{FECB}\u{FEE0}\u{FEF4}\u{FEDC}\u{FEE2}";
+        let visual: String = logical.chars().rev().collect();
+        assert!(reversed_lines(logical).is_empty());
+        assert_eq!(reversed_lines(&visual), [1]);
+        assert_eq!(repair(&visual), logical);
+    }
+
+    #[test]
+    fn test_mirrored_brackets_are_swapped() {
+        assert_eq!(reversed_lines("قال )رحمه الله( ذلك"), [1]);
+        assert_eq!(repair("قال )رحمه الله( ذلك"), "قال (رحمه الله) ذلك");
+        assert!(reversed_lines("قال (رحمه الله) ذلك").is_empty());
+    }
+
+    #[test]
+    fn test_comparisons_are_not_brackets() {
+        let text = "إذا كان x > 5 و y < 3 فالنتيجة صحيحة";
+        assert!(reversed_lines(text).is_empty());
+        assert_eq!(repair(text), text);
+    }
+}
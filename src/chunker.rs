@@ -1,54 +1,250 @@
-pub fn chunk_pdf(data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
-    if chunk_size == 0 {
-        return vec![data.to_vec()];
-    }
+use crate::error::{Error, Result};
+use crate::pages::PageRange;
+use crate::pdf_reader::PdfDocument;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+use std::collections::BTreeSet;
+
+/// Page attributes that may be inherited from ancestor `/Pages` nodes.
+const INHERITABLE_KEYS: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Bytes allowed per object besides its stream data when estimating the
+/// size of a sub-PDF: the dictionary, the `obj` header and the xref entry.
+const OBJECT_OVERHEAD: usize = 128;
 
-    data.chunks(chunk_size)
-        .map(|chunk| chunk.to_vec())
+/// Splits a document into one self-contained PDF per page range.
+pub fn split_pdf(document: &PdfDocument, ranges: &[PageRange]) -> Result<Vec<Vec<u8>>> {
+    ranges
+        .iter()
+        .map(|range| extract_pages(document, *range))
         .collect()
 }
 
+/// The pages of a range, ready to be copied, and the other objects they
+/// reach.
+struct Selection {
+    page_ids: Vec<ObjectId>,
+    pages: Vec<Dictionary>,
+    objects: BTreeSet<ObjectId>,
+}
+
+fn select(document: &Document, range: PageRange) -> Result<Selection> {
+    let pages = document.get_pages();
+    let page_ids = range
+        .pages()
+        .map(|number| {
+            pages
+                .get(&(number as u32))
+                .copied()
+                .ok_or(Error::PageOutOfRange {
+                    page: number,
+                    total_pages: pages.len(),
+                })
+        })
+        .collect::<Result<Vec<ObjectId>>>()?;
+    let excluded: BTreeSet<ObjectId> = pages
+        .values()
+        .copied()
+        .filter(|id| !page_ids.contains(id))
+        .collect();
+
+    let mut pending = Vec::new();
+    let mut selected_pages = Vec::with_capacity(page_ids.len());
+    for &page_id in &page_ids {
+        let mut page = document
+            .get_dictionary(page_id)
+            .map_err(|e| Error::parse(format!("Invalid page object {page_id:?}"), e))?
+            .clone();
+        for key in INHERITABLE_KEYS {
+            if !page.has(key) {
+                if let Some(value) = inherited_attribute(document, &page, key) {
+                    page.set(key, value);
+                }
+            }
+        }
+        page.remove(b"Annots");
+        collect_dictionary_references(&page, &mut pending);
+        selected_pages.push(page);
+    }
+
+    let mut objects = BTreeSet::new();
+    while let Some(id) = pending.pop() {
+        if excluded.contains(&id) || page_ids.contains(&id) || objects.contains(&id) {
+            continue;
+        }
+        // Dangling references are legal in PDF and read as null
+        let Ok(object) = document.get_object(id) else {
+            continue;
+        };
+        collect_references(object, &mut pending);
+        objects.insert(id);
+    }
+    Ok(Selection {
+        page_ids,
+        pages: selected_pages,
+        objects,
+    })
+}
+
+/// Roughly how many bytes [`extract_pages`] writes for `range`, from the
+/// stream lengths of the objects it copies, without building the PDF.
+pub fn estimated_size(document: &PdfDocument, range: PageRange) -> Result<usize> {
+    let selection = select(document, range)?;
+    let data: usize = selection
+        .objects
+        .iter()
+        .filter_map(|&id| document.get_object(id).ok()?.as_stream().ok())
+        .filter_map(|stream| document.stream_length(&stream.dict))
+        .sum();
+    Ok(data + (selection.pages.len() + selection.objects.len() + 2) * OBJECT_OVERHEAD)
+}
+
+/// Writes a new PDF that contains only the pages in `range`.
+///
+/// Every object reachable from the selected pages (content streams, fonts,
+/// images, form XObjects) is copied over, with its stream data read from
+/// the file, while the rest of the document is left behind. Annotations
+/// are dropped because links and outline targets would otherwise drag the
+/// other pages along with them.
+pub fn extract_pages(document: &PdfDocument, range: PageRange) -> Result<Vec<u8>> {
+    let selection = select(document, range)?;
+
+    let mut target = Document::with_version(document.version.clone());
+    target.max_id = document.max_id;
+    let pages_id = target.new_object_id();
+
+    for (&page_id, mut page) in selection.page_ids.iter().zip(selection.pages) {
+        page.set("Parent", pages_id);
+        target.objects.insert(page_id, Object::Dictionary(page));
+    }
+    for id in selection.objects {
+        target.objects.insert(id, document.load_object(id)?);
+    }
+
+    target.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => selection.page_ids.iter().map(|&id| Object::Reference(id)).collect::<Vec<_>>(),
+            "Count" => selection.page_ids.len() as i64,
+        }),
+    );
+    let catalog_id = target.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    target.trailer.set("Root", catalog_id);
+    target.renumber_objects();
+
+    let mut buffer = Vec::new();
+    target
+        .save_to(&mut buffer)
+        .map_err(Error::io(format!("Failed to write PDF for pages {range}")))?;
+    Ok(buffer)
+}
+
+fn inherited_attribute(document: &Document, page: &Dictionary, key: &[u8]) -> Option<Object> {
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(id) = parent {
+        let node = document.get_dictionary(id).ok()?;
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    None
+}
+
+/// Pushes every indirect reference held by `object`, without following
+/// `/Parent` links back up into the original page tree.
+fn collect_references(object: &Object, out: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => out.push(*id),
+        Object::Array(items) => items.iter().for_each(|item| collect_references(item, out)),
+        Object::Dictionary(dict) => collect_dictionary_references(dict, out),
+        Object::Stream(stream) => collect_dictionary_references(&stream.dict, out),
+        _ => {}
+    }
+}
+
+fn collect_dictionary_references(dict: &Dictionary, out: &mut Vec<ObjectId>) {
+    for (key, value) in dict.iter() {
+        if key.as_slice() != b"Parent" {
+            collect_references(value, out);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pdf_reader::{count_pages, load_document, sample_pdf};
+
+    fn page_text(pdf: &[u8], page: u32) -> String {
+        Document::load_mem(pdf)
+            .unwrap()
+            .extract_text(&[page])
+            .unwrap()
+    }
 
     #[test]
-    fn test_chunk_pdf_exact_chunks() {
-        let data = vec![1, 2, 3, 4, 5, 6];
-        let chunks = chunk_pdf(&data, 2);
+    fn test_extract_pages_keeps_only_range() {
+        let pdf = sample_pdf(10);
+        let document = load_document(&pdf).unwrap();
+        let part = extract_pages(&document, PageRange::new(4, 6)).unwrap();
 
-        assert_eq!(chunks.len(), 3);
-        assert_eq!(chunks[0], vec![1, 2]);
-        assert_eq!(chunks[1], vec![3, 4]);
-        assert_eq!(chunks[2], vec![5, 6]);
+        assert_eq!(count_pages(&part).unwrap(), 3);
+        assert!(page_text(&part, 1).contains("Page 4"));
+        assert!(page_text(&part, 3).contains("Page 6"));
     }
 
     #[test]
-    fn test_chunk_pdf_uneven_chunks() {
-        let data = vec![1, 2, 3, 4, 5];
-        let chunks = chunk_pdf(&data, 2);
+    fn test_extract_pages_copies_inherited_resources() {
+        let pdf = sample_pdf(2);
+        let document = load_document(&pdf).unwrap();
+        let part = extract_pages(&document, PageRange::new(2, 2)).unwrap();
 
-        assert_eq!(chunks.len(), 3);
-        assert_eq!(chunks[0], vec![1, 2]);
-        assert_eq!(chunks[1], vec![3, 4]);
-        assert_eq!(chunks[2], vec![5]);
+        let split = Document::load_mem(&part).unwrap();
+        let page_id = split.get_pages()[&1];
+        let fonts = split.get_page_fonts(page_id).unwrap();
+        assert!(fonts.contains_key(b"F1".as_slice()));
     }
 
     #[test]
-    fn test_chunk_pdf_zero_size() {
-        let data = vec![1, 2, 3];
-        let chunks = chunk_pdf(&data, 0);
+    fn test_split_pdf_one_part_per_range() {
+        let pdf = sample_pdf(7);
+        let document = load_document(&pdf).unwrap();
+        let ranges = [PageRange::new(1, 5), PageRange::new(6, 7)];
+        let parts = split_pdf(&document, &ranges).unwrap();
 
-        assert_eq!(chunks.len(), 1);
-        assert_eq!(chunks[0], vec![1, 2, 3]);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(count_pages(&parts[0]).unwrap(), 5);
+        assert_eq!(count_pages(&parts[1]).unwrap(), 2);
+        assert!(page_text(&parts[1], 2).contains("Page 7"));
     }
 
     #[test]
-    fn test_chunk_pdf_large_chunk_size() {
-        let data = vec![1, 2, 3];
-        let chunks = chunk_pdf(&data, 10);
+    fn test_estimated_size_is_close() {
+        let pdf = sample_pdf(10);
+        let document = load_document(&pdf).unwrap();
+        let range = PageRange::new(4, 6);
+        let actual = extract_pages(&document, range).unwrap().len();
+        let estimate = estimated_size(&document, range).unwrap();
+        assert!(
+            estimate > actual / 2 && estimate < actual * 2,
+            "{estimate} vs {actual}"
+        );
+    }
 
-        assert_eq!(chunks.len(), 1);
-        assert_eq!(chunks[0], vec![1, 2, 3]);
+    #[test]
+    fn test_extract_pages_out_of_range() {
+        let pdf = sample_pdf(3);
+        let document = load_document(&pdf).unwrap();
+        assert!(matches!(
+            extract_pages(&document, PageRange::new(3, 4)),
+            Err(Error::PageOutOfRange {
+                page: 4,
+                total_pages: 3
+            })
+        ));
     }
 }
@@ -0,0 +1,121 @@
+use crate::error::{Error, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Placeholder substituted for the API key in errors and logs.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Where the Gemini API key comes from.
+#[derive(Clone, PartialEq, Eq)]
+pub enum ApiKeySource {
+    /// The key itself, e.g. from `--api-key` or `GEMINI_API_KEY`.
+    Literal(String),
+    /// A file containing the key, such as a mounted secret.
+    File(PathBuf),
+    /// A credential helper command printing the key on stdout.
+    Command(String),
+}
+
+impl std::fmt::Debug for ApiKeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeySource::Literal(_) => f.debug_tuple("Literal").field(&REDACTED).finish(),
+            ApiKeySource::File(path) => f.debug_tuple("File").field(path).finish(),
+            ApiKeySource::Command(command) => f.debug_tuple("Command").field(command).finish(),
+        }
+    }
+}
+
+impl ApiKeySource {
+    /// Reads the key, trimming surrounding whitespace.
+    pub fn resolve(&self) -> Result<String> {
+        let key = match self {
+            ApiKeySource::Literal(key) => key.clone(),
+            ApiKeySource::File(path) => std::fs::read_to_string(path).map_err(Error::io(
+                format!("Failed to read API key file {}", path.display()),
+            ))?,
+            ApiKeySource::Command(command) => run_helper(command)?,
+        };
+
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(Error::InvalidInput(format!(
+                "{self:?} returned an empty API key"
+            )));
+        }
+        Ok(key.to_string())
+    }
+}
+
+fn run_helper(command: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(Error::io(format!(
+        "Failed to run credential helper '{command}'"
+    )))?;
+
+    if !output.status.success() {
+        return Err(Error::InvalidInput(format!(
+            "Credential helper '{command}' failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(|_| {
+        Error::InvalidInput(format!(
+            "Credential helper '{command}' printed a non-UTF-8 key"
+        ))
+    })
+}
+
+/// Replaces every occurrence of `secret` in `text` with [`REDACTED`].
+pub fn redact(text: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(secret, REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_key_from_file() {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "file_key\n").unwrap();
+        let source = ApiKeySource::File(file.path().to_path_buf());
+        assert_eq!(source.resolve().unwrap(), "file_key");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_from_command() {
+        let source = ApiKeySource::Command("echo helper_key".to_string());
+        assert_eq!(source.resolve().unwrap(), "helper_key");
+
+        let failing = ApiKeySource::Command("exit 3".to_string());
+        assert!(matches!(failing.resolve(), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_empty_key_is_rejected() {
+        let source = ApiKeySource::Literal("  ".to_string());
+        assert!(matches!(source.resolve(), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_debug_hides_literal_key() {
+        let source = ApiKeySource::Literal("secret_key".to_string());
+        assert!(!format!("{source:?}").contains("secret_key"));
+        assert_eq!(
+            redact("bad key secret_key", "secret_key"),
+            "bad key [REDACTED]"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error::{Error, Result};
+use crate::pages::{self, Page, PageRange};
+use crate::report::FailedRange;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// What extraction asks Gemini for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Plain text per page.
+    #[default]
+    Text,
+    /// A document model per page, see [`Block`], requested through a
+    /// `responseSchema`.
+    Json,
+    /// Markdown per page, see [`crate::markdown`].
+    Markdown,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "markdown" | "md" => Ok(Self::Markdown),
+            other => Err(Error::InvalidInput(format!(
+                "unknown output format '{other}' (expected text, json or markdown)"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Markdown => "markdown",
+        })
+    }
+}
+
+/// A structural element of a page. The blocks of a [`Page`] are in reading
+/// order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    /// A heading; level 1 is the most prominent.
+    Heading {
+        level: u8,
+        text: String,
+    },
+    Paragraph {
+        text: String,
+    },
+    Footnote {
+        text: String,
+    },
+    /// Table cells row by row, in the table's own (right-to-left) order.
+    Table {
+        rows: Vec<Vec<String>>,
+    },
+    /// The caption of a figure or table.
+    Caption {
+        text: String,
+    },
+}
+
+impl Block {
+    /// Applies `f` to every piece of text in the block.
+    pub fn map_text(&mut self, f: impl Fn(&str) -> String) {
+        match self {
+            Block::Heading { text, .. }
+            | Block::Paragraph { text }
+            | Block::Footnote { text }
+            | Block::Caption { text } => *text = f(text),
+            Block::Table { rows } => {
+                for cell in rows.iter_mut().flatten() {
+                    *cell = f(cell);
+                }
+            }
+        }
+    }
+
+    /// The block as plain text; table cells are separated by tabs.
+    pub fn plain_text(&self) -> String {
+        match self {
+            Block::Heading { text, .. }
+            | Block::Paragraph { text }
+            | Block::Footnote { text }
+            | Block::Caption { text } => text.clone(),
+            Block::Table { rows } => rows
+                .iter()
+                .map(|row| row.join("\t"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// The structured output of a whole job, as written by `--format json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    pub pages: Vec<Page>,
+    /// Page ranges missing from `pages`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FailedRange>,
+}
+
+/// The `responseSchema` of structured extraction.
+pub fn response_schema() -> Value {
+    json!({
+        "type": "OBJECT",
+        "properties": {
+            "pages": {
+                "type": "ARRAY",
+                "items": {
+                    "type": "OBJECT",
+                    "properties": {
+                        "page": {
+                            "type": "INTEGER",
+                            "description": "Position of the page in the PDF file, not the number printed on it"
+                        },
+                        "blocks": {
+                            "type": "ARRAY",
+                            "items": {
+                                "type": "OBJECT",
+                                "properties": {
+                                    "order": {
+                                        "type": "INTEGER",
+                                        "description": "Position of the block in reading order, starting at 0"
+                                    },
+                                    "type": {
+                                        "type": "STRING",
+                                        "format": "enum",
+                                        "enum": ["heading", "paragraph", "footnote", "table", "caption"]
+                                    },
+                                    "level": {
+                                        "type": "INTEGER",
+                                        "description": "Heading level, 1 for the most prominent"
+                                    },
+                                    "text": {"type": "STRING"},
+                                    "rows": {
+                                        "type": "ARRAY",
+                                        "description": "Table cells row by row",
+                                        "items": {"type": "ARRAY", "items": {"type": "STRING"}}
+                                    }
+                                },
+                                "required": ["order", "type"],
+                                "propertyOrdering": ["order", "type", "level", "text", "rows"]
+                            }
+                        }
+                    },
+                    "required": ["page", "blocks"],
+                    "propertyOrdering": ["page", "blocks"]
+                }
+            }
+        },
+        "required": ["pages"]
+    })
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    pages: Vec<SchemaPage>,
+}
+
+#[derive(Deserialize)]
+struct SchemaPage {
+    page: usize,
+    #[serde(default)]
+    blocks: Vec<SchemaBlock>,
+}
+
+#[derive(Deserialize)]
+struct SchemaBlock {
+    order: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    level: Option<u8>,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    rows: Vec<Vec<String>>,
+}
+
+impl SchemaBlock {
+    fn into_block(self) -> Result<Block> {
+        Ok(match self.kind.as_str() {
+            "heading" => Block::Heading {
+                level: self.level.unwrap_or(1).clamp(1, 6),
+                text: self.text,
+            },
+            "paragraph" => Block::Paragraph { text: self.text },
+            "footnote" => Block::Footnote { text: self.text },
+            "table" => Block::Table { rows: self.rows },
+            "caption" => Block::Caption { text: self.text },
+            other => {
+                return Err(Error::parse(
+                    "Failed to parse structured response",
+                    format!("unknown block type '{other}'"),
+                ))
+            }
+        })
+    }
+}
+
+/// Parses a structured response for `range` into pages whose blocks are
+/// sorted into reading order. Page numbers are validated like page
+/// markers, see [`pages::split_pages`].
+pub fn parse_structured(json: &str, range: PageRange) -> Result<Vec<Page>> {
+    let response: SchemaResponse = serde_json::from_str(json)
+        .map_err(|e| Error::parse("Failed to parse structured response", e))?;
+
+    let mut found = Vec::with_capacity(response.pages.len());
+    for page in response.pages {
+        let mut blocks = page.blocks;
+        blocks.sort_by_key(|block| block.order);
+        let blocks = blocks
+            .into_iter()
+            .map(SchemaBlock::into_block)
+            .collect::<Result<Vec<_>>>()?;
+        found.push(Page::structured(page.page, blocks));
+    }
+    // JSON arrays carry no page markers to keep in order, so only
+    // duplicates are an error
+    found.sort_by_key(|page| page.number);
+    pages::complete_pages(found, range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_structured_sorts_blocks_into_reading_order() {
+        let json = r#"{"pages": [{"page": 2, "blocks": [
+            {"order": 1, "type": "table", "rows": [["الاسم", "العمر"], ["زيد", "٣٠"]]},
+            {"order": 0, "type": "heading", "level": 2, "text": "الفصل الأول"},
+            {"order": 2, "type": "footnote", "text": "١ - حاشية"}
+        ]}]}"#;
+        let pages = parse_structured(json, PageRange::new(1, 2)).unwrap();
+
+        assert_eq!(pages[0], Page::new(1, ""));
+        assert_eq!(
+            pages[1].blocks,
+            [
+                Block::Heading {
+                    level: 2,
+                    text: "الفصل الأول".to_string()
+                },
+                Block::Table {
+                    rows: vec![
+                        vec!["الاسم".to_string(), "العمر".to_string()],
+                        vec!["زيد".to_string(), "٣٠".to_string()],
+                    ]
+                },
+                Block::Footnote {
+                    text: "١ - حاشية".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            pages[1].text,
+            "الفصل الأول\n\nالاسم\tالعمر\nزيد\t٣٠\n\n١ - حاشية"
+        );
+    }
+
+    #[test]
+    fn test_parse_structured_accepts_pages_out_of_order() {
+        let json = r#"{"pages": [
+            {"page": 3, "blocks": [{"order": 0, "type": "paragraph", "text": "ثالثة"}]},
+            {"page": 1, "blocks": [{"order": 0, "type": "paragraph", "text": "أولى"}]}
+        ]}"#;
+        let pages = parse_structured(json, PageRange::new(1, 3)).unwrap();
+        let numbers: Vec<usize> = pages.iter().map(|page| page.number).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert_eq!(pages[2].text, "ثالثة");
+    }
+
+    #[test]
+    fn test_parse_structured_rejects_foreign_pages() {
+        let json = r#"{"pages": [{"page": 9, "blocks": []}]}"#;
+        assert!(matches!(
+            parse_structured(json, PageRange::new(1, 2)),
+            Err(Error::PageMarkers { .. })
+        ));
+        assert!(parse_structured("not json", PageRange::new(1, 2)).is_err());
+    }
+
+    #[test]
+    fn test_blocks_round_trip_as_tagged_json() {
+        let block = Block::Caption {
+            text: "شكل ١".to_string(),
+        };
+        let json = serde_json::to_string(&block).unwrap();
+        assert_eq!(json, r#"{"type":"caption","text":"شكل ١"}"#);
+        assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::credentials::redact;
+use crate::pages::PageRange;
+use crate::report::FailedRange;
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the library.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The API key was rejected.
+    #[error("Authentication failed (HTTP {status}): {}", details.message)]
+    Auth {
+        status: StatusCode,
+        details: ApiErrorDetails,
+    },
+
+    /// A rate limit or quota was hit (`RESOURCE_EXHAUSTED`).
+    #[error("Quota exhausted (HTTP {status}): {}", details.message)]
+    QuotaExhausted {
+        status: StatusCode,
+        details: ApiErrorDetails,
+        /// Server-provided wait time from `Retry-After` or a `RetryInfo` detail.
+        retry_after: Option<Duration>,
+    },
+
+    /// The prompt or the response was blocked by Gemini's safety filters.
+    #[error("Blocked by safety filters: {reason}")]
+    SafetyBlock { reason: String },
+
+    /// The file could not be uploaded to the Files API.
+    #[error("Upload failed: {message}")]
+    UploadFailed {
+        message: String,
+        status: Option<StatusCode>,
+        details: Option<ApiErrorDetails>,
+        /// The request never got a response because of a timeout or a
+        /// broken connection, so repeating it may succeed.
+        retryable: bool,
+    },
+
+    /// An uploaded file never reached the `ACTIVE` state.
+    #[error("File {name} is not active (state: {state})")]
+    FileNotActive { name: String, state: String },
+
+    /// Gemini could not process an uploaded file.
+    #[error("Processing of file {name} failed: {message}")]
+    FileFailed { name: String, message: String },
+
+    /// A requested page does not exist in the document.
+    #[error("Page {page} is out of range (document has {total_pages} pages)")]
+    PageOutOfRange { page: usize, total_pages: usize },
+
+    /// The page markers of a response do not match the requested pages.
+    #[error("Invalid page markers in the response for pages {range}: {message}")]
+    PageMarkers { range: PageRange, message: String },
+
+    /// Markdown returned for a page is malformed beyond repair. `page` is
+    /// the page's position in the uploaded file.
+    #[error("Invalid Markdown for page {page}: {message}")]
+    InvalidMarkdown { page: usize, message: String },
+
+    /// Page ranges still failed after all retries, under
+    /// [`FailurePolicy::Fail`](crate::FailurePolicy::Fail).
+    #[error("{} page range(s) failed: {}", failed.len(), failed_list(failed))]
+    RangesFailed { failed: Vec<FailedRange> },
+
+    /// Any other non-success response from the Gemini API.
+    #[error("{operation} failed (HTTP {status}): {}", details.message)]
+    Api {
+        operation: String,
+        status: StatusCode,
+        details: ApiErrorDetails,
+        retry_after: Option<Duration>,
+    },
+
+    /// The request never got a response (timeout, connection failure, ...).
+    #[error("{operation}: {source}")]
+    Http {
+        operation: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// A response, PDF or state file could not be understood.
+    #[error("{context}: {message}")]
+    Parse { context: String, message: String },
+
+    /// The PDF is password protected.
+    #[error("Encrypted PDFs are not supported; remove the password protection first")]
+    EncryptedPdf,
+
+    /// Invalid user input such as a malformed page selection.
+    #[error("{0}")]
+    InvalidInput(String),
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Gemini's structured error payload, i.e. the `error` object of a
+/// non-success response.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiErrorDetails {
+    #[serde(default)]
+    pub code: Option<u16>,
+    #[serde(default)]
+    pub message: String,
+    /// Canonical status such as `RESOURCE_EXHAUSTED` or `INVALID_ARGUMENT`.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub details: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ErrorEnvelope {
+    error: ApiErrorDetails,
+}
+
+impl ApiErrorDetails {
+    /// Parses the payload, falling back to the raw body as the message.
+    pub fn from_body(body: &str) -> Self {
+        serde_json::from_str::<ErrorEnvelope>(body)
+            .map(|envelope| envelope.error)
+            .unwrap_or_else(|_| Self {
+                message: body.trim().to_string(),
+                ..Self::default()
+            })
+    }
+
+    /// `retryDelay` (e.g. `"37s"`) from a `google.rpc.RetryInfo` detail.
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.details
+            .iter()
+            .filter_map(|detail| detail["retryDelay"].as_str())
+            .find_map(|delay| delay.strip_suffix('s')?.parse::<f64>().ok())
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+    }
+
+    /// Whether a `google.rpc.ErrorInfo` detail reports an invalid API key.
+    fn is_invalid_api_key(&self) -> bool {
+        self.details
+            .iter()
+            .any(|detail| detail["reason"].as_str() == Some("API_KEY_INVALID"))
+    }
+}
+
+impl Error {
+    /// Builds an error from a non-success response, classifying auth and
+    /// quota failures.
+    pub(crate) async fn from_response(operation: impl Into<String>, response: Response) -> Self {
+        let status = response.status();
+        let header_delay = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        let details = ApiErrorDetails::from_body(&body);
+        let retry_after = header_delay.or_else(|| details.retry_delay());
+
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+            || details.is_invalid_api_key()
+        {
+            Error::Auth { status, details }
+        } else if status == StatusCode::TOO_MANY_REQUESTS
+            || details.status.as_deref() == Some("RESOURCE_EXHAUSTED")
+        {
+            Error::QuotaExhausted {
+                status,
+                details,
+                retry_after,
+            }
+        } else {
+            Error::Api {
+                operation: operation.into(),
+                status,
+                details,
+                retry_after,
+            }
+        }
+    }
+
+    /// Turns a failure during upload into [`Error::UploadFailed`], keeping
+    /// auth and quota errors as they are.
+    pub(crate) fn into_upload_error(self) -> Self {
+        match self {
+            Error::Api {
+                operation,
+                status,
+                details,
+                ..
+            } => Error::UploadFailed {
+                message: format!("{operation}: {}", details.message),
+                status: Some(status),
+                details: Some(details),
+                retryable: false,
+            },
+            Error::Http { operation, source } => Error::UploadFailed {
+                message: format!("{operation}: {source}"),
+                status: None,
+                details: None,
+                retryable: is_transport_failure(&source),
+            },
+            other => other,
+        }
+    }
+
+    pub(crate) fn http(operation: impl Into<String>) -> impl FnOnce(reqwest::Error) -> Self {
+        let operation = operation.into();
+        move |source| Error::Http { operation, source }
+    }
+
+    pub(crate) fn io(context: impl Into<String>) -> impl FnOnce(std::io::Error) -> Self {
+        let context = context.into();
+        move |source| Error::Io { context, source }
+    }
+
+    pub(crate) fn parse(context: impl Into<String>, message: impl ToString) -> Self {
+        Error::Parse {
+            context: context.into(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Removes `secret` from every message and drops request URLs, so the
+    /// API key never ends up in logs or error reports.
+    pub(crate) fn redact(self, secret: &str) -> Self {
+        let clean = |text: String| redact(&text, secret);
+        let clean_details = |details: ApiErrorDetails| ApiErrorDetails {
+            message: redact(&details.message, secret),
+            ..details
+        };
+        match self {
+            Error::Auth { status, details } => Error::Auth {
+                status,
+                details: clean_details(details),
+            },
+            Error::QuotaExhausted {
+                status,
+                details,
+                retry_after,
+            } => Error::QuotaExhausted {
+                status,
+                details: clean_details(details),
+                retry_after,
+            },
+            Error::UploadFailed {
+                message,
+                status,
+                details,
+                retryable,
+            } => Error::UploadFailed {
+                message: clean(message),
+                status,
+                details: details.map(clean_details),
+                retryable,
+            },
+            Error::Api {
+                operation,
+                status,
+                details,
+                retry_after,
+            } => Error::Api {
+                operation,
+                status,
+                details: clean_details(details),
+                retry_after,
+            },
+            Error::Http { operation, source } => Error::Http {
+                operation,
+                source: source.without_url(),
+            },
+            Error::Parse { context, message } => Error::Parse {
+                context,
+                message: clean(message),
+            },
+            Error::InvalidInput(message) => Error::InvalidInput(clean(message)),
+            other => other,
+        }
+    }
+
+    /// HTTP status of the failed request, if there was a response.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Auth { status, .. }
+            | Error::QuotaExhausted { status, .. }
+            | Error::Api { status, .. } => Some(*status),
+            Error::UploadFailed { status, .. } => *status,
+            Error::Http { source, .. } => source.status(),
+            _ => None,
+        }
+    }
+
+    /// Gemini's structured error payload, if the API returned one.
+    pub fn api_details(&self) -> Option<&ApiErrorDetails> {
+        match self {
+            Error::Auth { details, .. }
+            | Error::QuotaExhausted { details, .. }
+            | Error::Api { details, .. } => Some(details),
+            Error::UploadFailed { details, .. } => details.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns `Some(server_hint)` when retrying the request may succeed:
+    /// rate limits, server errors (500, 502, 503, 504), timeouts and
+    /// connection failures, also while uploading, and responses with
+    /// invalid page markers or Markdown.
+    pub fn retry_hint(&self) -> Option<Option<Duration>> {
+        match self {
+            Error::QuotaExhausted { retry_after, .. } => Some(*retry_after),
+            Error::Api {
+                status,
+                retry_after,
+                ..
+            } if is_transient(*status) => Some(*retry_after),
+            Error::UploadFailed {
+                status: Some(status),
+                ..
+            } if is_transient(*status) => Some(None),
+            Error::UploadFailed {
+                retryable: true, ..
+            } => Some(None),
+            Error::Http { source, .. } if is_transport_failure(source) => Some(None),
+            // The model may well get the markers right on another attempt
+            Error::PageMarkers { .. } | Error::InvalidMarkdown { .. } => Some(None),
+            _ => None,
+        }
+    }
+}
+
+/// Timeouts and connection failures, which say nothing about the request
+/// itself.
+fn is_transport_failure(source: &reqwest::Error) -> bool {
+    source.is_timeout() || source.is_connect() || source.is_request()
+}
+
+fn failed_list(failed: &[FailedRange]) -> String {
+    failed
+        .iter()
+        .map(|failed| failed.range().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 500 | 502 | 503 | 504)
+}
+
+/// Parses a `Retry-After` header given either in seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        assert_eq!(parse_retry_after("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_retry_delay_from_error_details() {
+        let body = r#"{
+            "error": {
+                "code": 429,
+                "message": "Quota exceeded",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {"@type": "type.googleapis.com/google.rpc.QuotaFailure"},
+                    {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "37s"}
+                ]
+            }
+        }"#;
+        let details = ApiErrorDetails::from_body(body);
+        assert_eq!(details.code, Some(429));
+        assert_eq!(details.status.as_deref(), Some("RESOURCE_EXHAUSTED"));
+        assert_eq!(details.retry_delay(), Some(Duration::from_secs(37)));
+    }
+
+    #[test]
+    fn test_unstructured_error_body() {
+        let details = ApiErrorDetails::from_body("Invalid API key\n");
+        assert_eq!(details.message, "Invalid API key");
+        assert_eq!(details.retry_delay(), None);
+    }
+
+    #[test]
+    fn test_retry_hint_classification() {
+        let api_error = |status: u16| Error::Api {
+            operation: "Generate".to_string(),
+            status: StatusCode::from_u16(status).unwrap(),
+            details: ApiErrorDetails::default(),
+            retry_after: None,
+        };
+        assert_eq!(api_error(503).retry_hint(), Some(None));
+        assert_eq!(api_error(400).retry_hint(), None);
+        assert_eq!(Error::EncryptedPdf.retry_hint(), None);
+
+        let quota = Error::QuotaExhausted {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            details: ApiErrorDetails::default(),
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(quota.retry_hint(), Some(Some(Duration::from_secs(5))));
+    }
+
+    #[test]
+    fn test_redact_removes_api_key() {
+        let error = Error::Auth {
+            status: StatusCode::BAD_REQUEST,
+            details: ApiErrorDetails::from_body("API key secret_key not valid"),
+        }
+        .redact("secret_key");
+        assert_eq!(
+            error.to_string(),
+            "Authentication failed (HTTP 400 Bad Request): API key [REDACTED] not valid"
+        );
+    }
+}
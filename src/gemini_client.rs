@@ -1,9 +1,22 @@
-use anyhow::{Context, Result};
+use crate::document;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::normalize::Diacritics;
+use crate::pages::{self, page_marker, Page, PageRange};
+use crate::rate_limit::{RateLimiter, RateLimits};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
 use reqwest::{
     header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
-    Client,
+    Body, Client,
 };
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::{Cursor, SeekFrom};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
 
 #[derive(Debug, Serialize)]
 pub struct GeminiRequest {
@@ -50,95 +63,487 @@ struct FileMetadata {
 
 #[derive(Debug, Deserialize)]
 struct FileUploadResponse {
-    file: FileInfo,
+    file: UploadedFile,
 }
 
 #[derive(Debug, Deserialize)]
-struct FileInfo {
-    uri: String,
-    #[allow(dead_code)]
-    name: String,
+#[serde(rename_all = "camelCase")]
+struct ListFilesResponse {
+    #[serde(default)]
+    files: Vec<UploadedFile>,
+    next_page_token: Option<String>,
+}
+
+/// A file stored through the Gemini Files API.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedFile {
+    /// Resource name, e.g. `files/abc123`.
+    pub name: String,
+    pub uri: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_int64")]
+    pub size_bytes: Option<u64>,
+    /// Base64-encoded SHA-256 of the content, as reported by Gemini.
+    #[serde(default)]
+    pub sha256_hash: Option<String>,
+    #[serde(default)]
+    pub create_time: Option<DateTime<Utc>>,
+    /// When Gemini deletes the file; uploads live for 48 hours.
+    #[serde(default)]
+    pub expiration_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub state: FileState,
+    /// Why processing failed, set when `state` is [`FileState::Failed`].
+    #[serde(default)]
+    pub error: Option<FileError>,
+}
+
+/// Processing state of an uploaded file. Only `ACTIVE` files can be used
+/// in `generateContent` requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FileState {
+    Processing,
+    Active,
+    Failed,
+    #[default]
+    #[serde(other)]
+    StateUnspecified,
+}
+
+impl std::fmt::Display for FileState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileState::StateUnspecified => "STATE_UNSPECIFIED",
+            FileState::Processing => "PROCESSING",
+            FileState::Active => "ACTIVE",
+            FileState::Failed => "FAILED",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FileError {
+    #[serde(default)]
+    pub code: Option<i32>,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Reads `len` bytes starting at `offset`.
+async fn read_chunk<R>(reader: &mut R, offset: u64, len: usize) -> Result<Bytes>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let mut buffer = vec![0; len];
+    reader
+        .seek(SeekFrom::Start(offset))
+        .await
+        .map_err(Error::io("Failed to read PDF for upload"))?;
+    reader
+        .read_exact(&mut buffer)
+        .await
+        .map_err(Error::io("Failed to read PDF for upload"))?;
+    Ok(Bytes::from(buffer))
+}
+
+/// Reads an `int64` field, which the API encodes as a JSON string.
+fn deserialize_int64<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int64 {
+        Number(u64),
+        Text(String),
+    }
+
+    match Option::<Int64>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Int64::Number(value)) => Ok(Some(value)),
+        Some(Int64::Text(text)) => text.parse().map(Some).map_err(serde::de::Error::custom),
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct GenerationConfig {
     #[serde(rename = "responseMimeType")]
     response_mime_type: String,
+    #[serde(rename = "responseSchema", skip_serializing_if = "Option::is_none")]
+    response_schema: Option<serde_json::Value>,
+}
+
+impl GenerationConfig {
+    fn text() -> Self {
+        Self {
+            response_mime_type: "text/plain".to_string(),
+            response_schema: None,
+        }
+    }
+}
+
+/// Tells Gemini how to mark the start of each page, see [`page_marker`].
+fn page_marker_instruction(first_page: usize) -> String {
+    format!(
+        "Start each page with a line containing only <<<PAGE N>>> (for example {}), \
+            where N is the position of the page in this PDF file, not the page number printed on it.",
+        page_marker(first_page)
+    )
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiResponse {
+    #[serde(default)]
     candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    total_token_count: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct Candidate {
-    content: ResponseContent,
+    content: Option<ResponseContent>,
+    finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ResponseContent {
+    #[serde(default)]
     parts: Vec<ResponsePart>,
 }
 
+/// Gemini bills each PDF page as 258 input tokens.
+const TOKENS_PER_PAGE: u64 = 258;
+
+/// Output allowance added to token estimates.
+const OUTPUT_TOKEN_ESTIMATE: u64 = 8_192;
+
+/// Finish reasons that mean the output was withheld by a content filter.
+const BLOCKED_FINISH_REASONS: [&str; 5] = [
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+];
+
+impl GeminiResponse {
+    /// Returns the text of the first candidate, reporting safety blocks.
+    fn into_text(self, context: &str) -> Result<String> {
+        if let Some(reason) = self.prompt_feedback.and_then(|f| f.block_reason) {
+            return Err(Error::SafetyBlock { reason });
+        }
+        let candidate = self
+            .candidates
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::parse(context, "response contains no candidates"))?;
+        if let Some(reason) = candidate
+            .finish_reason
+            .filter(|reason| BLOCKED_FINISH_REASONS.contains(&reason.as_str()))
+        {
+            return Err(Error::SafetyBlock { reason });
+        }
+        candidate
+            .content
+            .and_then(|content| content.parts.into_iter().next())
+            .map(|part| part.text)
+            .ok_or_else(|| Error::parse(context, "No text found in response"))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct ResponsePart {
     text: String,
 }
 
+/// How failed requests are retried.
+///
+/// Rate limits (429), server errors (500, 502, 503, 504), timeouts and
+/// connection failures are retried with capped exponential backoff and
+/// jitter. A wait time sent by the server takes precedence when longer.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Backoff before retry number `attempt` (starting at 1): the capped
+    /// exponential delay, with the upper half randomised.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let capped = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        capped / 2 + capped.mul_f64(fastrand::f64() / 2.0)
+    }
+
+    /// Runs `operation` until it succeeds, fails with a non-retryable
+    /// error, or runs out of retries. `on_retry` is told about each wait.
+    pub async fn run<T, F, Fut>(
+        &self,
+        mut operation: F,
+        mut on_retry: impl FnMut(u32, Duration, &Error),
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let error = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let Some(server_hint) = error.retry_hint() else {
+                return Err(error);
+            };
+            attempt += 1;
+            if attempt > self.max_retries {
+                return Err(error);
+            }
+
+            let backoff = self.backoff(attempt);
+            let delay = server_hint.map_or(backoff, |hint| hint.max(backoff));
+            on_retry(attempt, delay, &error);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+pub const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+pub const DEFAULT_API_VERSION: &str = "v1beta";
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+
+/// Called with the number of bytes uploaded so far.
+pub type UploadProgress = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Granularity of upload progress reports.
+const PROGRESS_STEP: usize = 64 * 1024;
+
+/// Every upload chunk but the last must be a multiple of this size.
+const UPLOAD_CHUNK_GRANULARITY: usize = 256 * 1024;
+
+/// How often a failed chunk is resumed before the upload is given up.
+const MAX_UPLOAD_RESUMES: u32 = 3;
+
+/// Header carrying the API key, which keeps it out of URLs and proxy logs.
+const API_KEY_HEADER: &str = "x-goog-api-key";
+
 pub struct GeminiClient {
     api_key: String,
+    /// `api_key` as a sensitive header value, hidden from `Debug` output.
+    api_key_header: HeaderValue,
     client: Client,
     base_url: String,
+    api_version: String,
+    model: String,
+    timeout: Duration,
+    upload_timeout: Duration,
+    activation_timeout: Duration,
+    poll_interval: Duration,
+    upload_chunk_size: usize,
+    rate_limiter: Arc<RateLimiter>,
+    diacritics: Diacritics,
 }
 
-impl GeminiClient {
-    pub fn new(api_key: &str) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300)) // 5 minute timeout
-            .build()
-            .unwrap();
+/// Builder for [`GeminiClient`], created with [`GeminiClient::builder`].
+pub struct GeminiClientBuilder {
+    api_key: String,
+    client: Option<Client>,
+    base_url: String,
+    api_version: String,
+    model: String,
+    timeout: Duration,
+    upload_timeout: Duration,
+    activation_timeout: Duration,
+    poll_interval: Duration,
+    upload_chunk_size: usize,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    diacritics: Diacritics,
+}
 
-        Self {
-            api_key: api_key.to_string(),
+impl GeminiClientBuilder {
+    /// Model used for extraction, e.g. `gemini-2.5-pro`.
+    pub fn model(mut self, model: &str) -> Self {
+        self.model = model.trim_start_matches("models/").to_string();
+        self
+    }
+
+    /// API version segment of every URL, e.g. `v1` or `v1beta`.
+    pub fn api_version(mut self, api_version: &str) -> Self {
+        self.api_version = api_version.to_string();
+        self
+    }
+
+    /// Root URL of the API, e.g. a local proxy such as `http://localhost:8080`.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Timeout of a single page-range extraction request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Timeout of each upload request.
+    pub fn upload_timeout(mut self, timeout: Duration) -> Self {
+        self.upload_timeout = timeout;
+        self
+    }
+
+    /// How long to wait for an uploaded file to become `ACTIVE`.
+    pub fn activation_timeout(mut self, timeout: Duration) -> Self {
+        self.activation_timeout = timeout;
+        self
+    }
+
+    /// Delay between two state checks of a file that is still processing.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Bytes sent per resumable upload request, rounded up to a multiple
+    /// of 256 KiB as Gemini expects for every chunk but the last.
+    pub fn upload_chunk_size(mut self, bytes: usize) -> Self {
+        self.upload_chunk_size = bytes.max(1).next_multiple_of(UPLOAD_CHUNK_GRANULARITY);
+        self
+    }
+
+    /// Sends requests through a preconfigured HTTP client (proxies, TLS
+    /// roots, connection pools, ...).
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Shares `rate_limiter` with this client, so that several clients can
+    /// draw from the same request and token budgets.
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// How extraction prompts ask for diacritics; kept as printed by
+    /// default.
+    pub fn diacritics(mut self, diacritics: Diacritics) -> Self {
+        self.diacritics = diacritics;
+        self
+    }
+
+    pub fn build(self) -> Result<GeminiClient> {
+        let mut api_key_header = HeaderValue::from_str(&self.api_key).map_err(|_| {
+            Error::InvalidInput("API key contains characters not allowed in a header".to_string())
+        })?;
+        api_key_header.set_sensitive(true);
+
+        let client = match self.client {
+            Some(client) => client,
+            None => Client::builder()
+                .build()
+                .map_err(Error::http("Failed to build HTTP client"))?,
+        };
+
+        Ok(GeminiClient {
+            api_key: self.api_key,
+            api_key_header,
             client,
-            base_url: "https://generativelanguage.googleapis.com".to_string(),
-        }
+            base_url: self.base_url,
+            api_version: self.api_version,
+            model: self.model,
+            timeout: self.timeout,
+            upload_timeout: self.upload_timeout,
+            activation_timeout: self.activation_timeout,
+            poll_interval: self.poll_interval,
+            upload_chunk_size: self.upload_chunk_size,
+            rate_limiter: self
+                .rate_limiter
+                .unwrap_or_else(|| Arc::new(RateLimiter::new(RateLimits::default()))),
+            diacritics: self.diacritics,
+        })
     }
+}
 
-    #[cfg(test)]
-    fn with_base_url(api_key: &str, base_url: &str) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300))
-            .build()
-            .unwrap();
+impl GeminiClient {
+    pub fn new(api_key: &str) -> Result<Self> {
+        Self::builder(api_key).build()
+    }
 
-        Self {
+    pub fn builder(api_key: &str) -> GeminiClientBuilder {
+        GeminiClientBuilder {
             api_key: api_key.to_string(),
-            client,
-            base_url: base_url.to_string(),
+            client: None,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            model: DEFAULT_MODEL.to_string(),
+            timeout: Duration::from_secs(120),
+            upload_timeout: Duration::from_secs(300), // 5 minute timeout
+            activation_timeout: Duration::from_secs(600),
+            poll_interval: Duration::from_secs(2),
+            upload_chunk_size: 8 * 1024 * 1024,
+            rate_limiter: None,
+            diacritics: Diacritics::default(),
         }
     }
 
-    async fn upload_file(&self, pdf_data: &[u8], display_name: &str) -> Result<String> {
-        let num_bytes = pdf_data.len();
-
-        use colored::*;
-        println!(
-            "\n{} {}",
-            "🔐".cyan(),
-            format!("Initiating secure file transfer for: {display_name}")
-                .cyan()
-                .bold()
-        );
-        println!(
-            "  {} File size: {} bytes",
-            "📊".yellow(),
-            num_bytes.to_string().green()
-        );
+    pub fn model(&self) -> &str {
+        &self.model
+    }
 
+    async fn upload_file<R>(
+        &self,
+        mut reader: R,
+        num_bytes: u64,
+        display_name: &str,
+        progress: UploadProgress,
+    ) -> Result<UploadedFile>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
         // Step 1: Initial resumable request
-        let upload_init_url = format!("{}/upload/v1beta/files?key={}", self.base_url, self.api_key);
+        let upload_init_url = format!("{}/upload/{}/files", self.base_url, self.api_version);
 
         let file_metadata = FileUploadRequest {
             file: FileMetadata {
@@ -147,6 +552,7 @@ impl GeminiClient {
         };
 
         let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, self.api_key_header.clone());
         headers.insert(
             "X-Goog-Upload-Protocol",
             HeaderValue::from_static("resumable"),
@@ -154,7 +560,7 @@ impl GeminiClient {
         headers.insert("X-Goog-Upload-Command", HeaderValue::from_static("start"));
         headers.insert(
             "X-Goog-Upload-Header-Content-Length",
-            HeaderValue::from_str(&num_bytes.to_string())?,
+            HeaderValue::from(num_bytes),
         );
         headers.insert(
             "X-Goog-Upload-Header-Content-Type",
@@ -165,109 +571,428 @@ impl GeminiClient {
         let init_response = self
             .client
             .post(&upload_init_url)
+            .timeout(self.upload_timeout)
             .headers(headers)
             .json(&file_metadata)
             .send()
             .await
-            .context("Failed to initiate file upload")?;
+            .map_err(Error::http("Failed to initiate file upload"))
+            .map_err(Error::into_upload_error)?;
 
         if !init_response.status().is_success() {
-            let error_text = init_response.text().await?;
-            anyhow::bail!("Failed to initiate upload: {}", error_text);
+            return Err(
+                Error::from_response("Failed to initiate upload", init_response)
+                    .await
+                    .into_upload_error(),
+            );
         }
 
         // Get upload URL from response headers
         let upload_url = init_response
             .headers()
             .get("x-goog-upload-url")
-            .ok_or_else(|| anyhow::anyhow!("No upload URL in response"))?
-            .to_str()?
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Error::UploadFailed {
+                message: "No upload URL in response".to_string(),
+                status: None,
+                details: None,
+                retryable: false,
+            })?
             .to_string();
 
-        println!(
-            "\n{} {}",
-            "🚀".green(),
-            "Upload session initialized successfully!".green().bold()
-        );
-        println!("  {} Session URL acquired", "✓".green());
-        println!("  {} Beginning data transmission...", "📡".cyan());
-
-        // Step 2: Upload the actual bytes
-        let mut upload_headers = HeaderMap::new();
-        upload_headers.insert(
-            CONTENT_LENGTH,
-            HeaderValue::from_str(&num_bytes.to_string())?,
-        );
-        upload_headers.insert("X-Goog-Upload-Offset", HeaderValue::from_static("0"));
-        upload_headers.insert(
-            "X-Goog-Upload-Command",
-            HeaderValue::from_static("upload, finalize"),
-        );
+        // Step 2: Upload the bytes chunk by chunk, resuming from the last
+        // acknowledged offset when a chunk fails
+        let mut offset = 0;
+        let mut resumes = 0;
+        let upload_response = loop {
+            let end = (offset + self.upload_chunk_size as u64).min(num_bytes);
+            let command = if end == num_bytes {
+                "upload, finalize"
+            } else {
+                "upload"
+            };
+            // Only one chunk is held in memory, however large the file is
+            let chunk = read_chunk(&mut reader, offset, (end - offset) as usize).await?;
+            let result = self
+                .upload_chunk(&upload_url, chunk, offset, command, &progress)
+                .await;
+
+            match result {
+                Ok(response) if end == num_bytes => break response,
+                Ok(_) => offset = end,
+                Err(error) if error.retry_hint().is_some() && resumes < MAX_UPLOAD_RESUMES => {
+                    resumes += 1;
+                    offset = self.query_upload_offset(&upload_url).await?;
+                    progress(offset);
+                }
+                Err(error) => return Err(error),
+            }
+        };
+
+        let file_info: FileUploadResponse = upload_response
+            .json()
+            .await
+            .map_err(|e| Error::parse("Failed to parse upload response", e))?;
+
+        Ok(file_info.file)
+    }
+
+    /// Sends one chunk of a resumable upload, counting the bytes as they are
+    /// handed to the connection.
+    async fn upload_chunk(
+        &self,
+        upload_url: &str,
+        chunk: Bytes,
+        offset: u64,
+        command: &'static str,
+        progress: &UploadProgress,
+    ) -> Result<reqwest::Response> {
+        let len = chunk.len();
+        let progress = progress.clone();
+        let mut sent = offset;
+        let pieces = (0..len)
+            .step_by(PROGRESS_STEP)
+            .map(move |start| chunk.slice(start..(start + PROGRESS_STEP).min(len)));
+        let body = stream::iter(pieces).map(move |piece| {
+            sent += piece.len() as u64;
+            progress(sent);
+            Ok::<_, std::io::Error>(piece)
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, self.api_key_header.clone());
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+        headers.insert("X-Goog-Upload-Offset", HeaderValue::from(offset));
+        headers.insert("X-Goog-Upload-Command", HeaderValue::from_static(command));
 
-        let upload_response = self
+        let response = self
             .client
-            .post(&upload_url)
-            .headers(upload_headers)
-            .body(pdf_data.to_vec())
+            .post(upload_url)
+            .timeout(self.upload_timeout)
+            .headers(headers)
+            .body(Body::wrap_stream(body))
             .send()
             .await
-            .context("Failed to upload file data")?;
+            .map_err(Error::http("Failed to upload file data"))
+            .map_err(Error::into_upload_error)?;
 
-        if !upload_response.status().is_success() {
-            let error_text = upload_response.text().await?;
-            anyhow::bail!("Failed to upload file: {}", error_text);
+        if !response.status().is_success() {
+            return Err(Error::from_response("Failed to upload file", response)
+                .await
+                .into_upload_error());
         }
+        Ok(response)
+    }
 
-        let file_info: FileUploadResponse = upload_response
-            .json()
+    /// Asks the upload session how many bytes it has received.
+    async fn query_upload_offset(&self, upload_url: &str) -> Result<u64> {
+        let response = self
+            .client
+            .post(upload_url)
+            .timeout(self.upload_timeout)
+            .header(API_KEY_HEADER, self.api_key_header.clone())
+            .header("X-Goog-Upload-Command", "query")
+            .header(CONTENT_LENGTH, 0)
+            .send()
             .await
-            .context("Failed to parse upload response")?;
+            .map_err(Error::http("Failed to query upload status"))
+            .map_err(Error::into_upload_error)?;
 
-        println!(
-            "\n{} {}",
-            "🎉".green(),
-            "File uploaded successfully!".green().bold()
-        );
-        println!(
-            "  {} Upload ID: {}",
-            "🆔".cyan(),
-            file_info.file.name.yellow()
-        );
-        println!(
-            "  {} Cloud URI: {}",
-            "☁️".cyan(),
-            file_info.file.uri.blue().underline()
-        );
-        println!(
-            "  {} Status: {}",
-            "✅".green(),
-            "Ready for AI processing".green()
-        );
+        if !response.status().is_success() {
+            return Err(
+                Error::from_response("Failed to query upload status", response)
+                    .await
+                    .into_upload_error(),
+            );
+        }
+        response
+            .headers()
+            .get("x-goog-upload-size-received")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| Error::UploadFailed {
+                message: "Upload status has no received size".to_string(),
+                status: None,
+                details: None,
+                retryable: false,
+            })
+    }
 
-        Ok(file_info.file.uri)
+    pub async fn upload_full_pdf(
+        &self,
+        pdf_data: &[u8],
+        display_name: &str,
+    ) -> Result<UploadedFile> {
+        self.upload_with_progress(pdf_data, display_name, Arc::new(|_| {}))
+            .await
     }
 
-    pub async fn upload_full_pdf(&self, pdf_data: &[u8], display_name: &str) -> Result<String> {
-        self.upload_file(pdf_data, display_name).await
+    /// Uploads the PDF and waits until it is `ACTIVE`, reporting the number
+    /// of bytes sent so far to `progress`.
+    pub async fn upload_with_progress(
+        &self,
+        pdf_data: &[u8],
+        display_name: &str,
+        progress: UploadProgress,
+    ) -> Result<UploadedFile> {
+        let len = pdf_data.len() as u64;
+        self.upload_reader(Cursor::new(pdf_data), len, display_name, progress)
+            .await
     }
 
+    /// Uploads `num_bytes` bytes read from `reader`, one chunk at a time, and
+    /// waits until the file is `ACTIVE`. Memory use is bounded by the upload
+    /// chunk size, so arbitrarily large files can be streamed from disk.
+    pub async fn upload_reader<R>(
+        &self,
+        reader: R,
+        num_bytes: u64,
+        display_name: &str,
+        progress: UploadProgress,
+    ) -> Result<UploadedFile>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
+        let file = self
+            .upload_file(reader, num_bytes, display_name, progress)
+            .await
+            .map_err(|e| e.redact(&self.api_key))?;
+        self.wait_until_active(file).await
+    }
+
+    /// Fetches the metadata of an uploaded file by its resource name
+    /// (`files/abc123`).
+    pub async fn get_file(&self, name: &str) -> Result<UploadedFile> {
+        async {
+            let url = format!("{}/{}/{}", self.base_url, self.api_version, name);
+            let response = self
+                .client
+                .get(&url)
+                .header(API_KEY_HEADER, self.api_key_header.clone())
+                .timeout(self.upload_timeout)
+                .send()
+                .await
+                .map_err(Error::http(format!("Failed to get file {name}")))?;
+
+            if !response.status().is_success() {
+                return Err(Error::from_response(format!("Get file {name}"), response).await);
+            }
+
+            response
+                .json()
+                .await
+                .map_err(|e| Error::parse(format!("Failed to parse metadata of {name}"), e))
+        }
+        .await
+        .map_err(|e| e.redact(&self.api_key))
+    }
+
+    /// Lists every file stored in the project, following pagination.
+    pub async fn list_files(&self) -> Result<Vec<UploadedFile>> {
+        async {
+            let url = format!("{}/{}/files", self.base_url, self.api_version);
+            let mut files = Vec::new();
+            let mut page_token: Option<String> = None;
+            loop {
+                let mut request = self
+                    .client
+                    .get(&url)
+                    .header(API_KEY_HEADER, self.api_key_header.clone())
+                    .query(&[("pageSize", "100")])
+                    .timeout(self.upload_timeout);
+                if let Some(token) = &page_token {
+                    request = request.query(&[("pageToken", token)]);
+                }
+                let response = request
+                    .send()
+                    .await
+                    .map_err(Error::http("Failed to list files"))?;
+
+                if !response.status().is_success() {
+                    return Err(Error::from_response("List files", response).await);
+                }
+
+                let page: ListFilesResponse = response
+                    .json()
+                    .await
+                    .map_err(|e| Error::parse("Failed to parse file list", e))?;
+                files.extend(page.files);
+                match page.next_page_token.filter(|token| !token.is_empty()) {
+                    Some(token) => page_token = Some(token),
+                    None => return Ok(files),
+                }
+            }
+        }
+        .await
+        .map_err(|e| e.redact(&self.api_key))
+    }
+
+    /// Deletes an uploaded file by its resource name (`files/abc123`).
+    pub async fn delete_file(&self, name: &str) -> Result<()> {
+        async {
+            let url = format!("{}/{}/{}", self.base_url, self.api_version, name);
+            let response = self
+                .client
+                .delete(&url)
+                .header(API_KEY_HEADER, self.api_key_header.clone())
+                .timeout(self.upload_timeout)
+                .send()
+                .await
+                .map_err(Error::http(format!("Failed to delete file {name}")))?;
+
+            if !response.status().is_success() {
+                return Err(Error::from_response(format!("Delete file {name}"), response).await);
+            }
+            Ok(())
+        }
+        .await
+        .map_err(|e| e.redact(&self.api_key))
+    }
+
+    /// Polls the file until Gemini has finished processing it. Fails with
+    /// [`Error::FileFailed`] if processing failed and with
+    /// [`Error::FileNotActive`] if the file is still not `ACTIVE` after the
+    /// activation timeout.
+    pub async fn wait_until_active(&self, mut file: UploadedFile) -> Result<UploadedFile> {
+        let deadline = tokio::time::Instant::now() + self.activation_timeout;
+        loop {
+            match file.state {
+                FileState::Active => return Ok(file),
+                FileState::Failed => {
+                    return Err(Error::FileFailed {
+                        message: file
+                            .error
+                            .map(|error| error.message)
+                            .unwrap_or_else(|| "no error details".to_string()),
+                        name: file.name,
+                    })
+                }
+                FileState::Processing | FileState::StateUnspecified => {}
+            }
+
+            if tokio::time::Instant::now() + self.poll_interval > deadline {
+                return Err(Error::FileNotActive {
+                    name: file.name,
+                    state: file.state.to_string(),
+                });
+            }
+            tokio::time::sleep(self.poll_interval).await;
+            file = self.get_file(&file.name).await?;
+        }
+    }
+
+    /// Extracts pages `start_page` to `end_page` of an uploaded PDF, one
+    /// [`Page`] per requested page.
+    ///
+    /// Gemini is asked to put a [`page_marker`] before each page, and the
+    /// markers of the response are validated against the requested range.
     pub async fn extract_page_range(
         &self,
         file_uri: &str,
         start_page: usize,
         end_page: usize,
+    ) -> Result<Vec<Page>> {
+        let range = PageRange::new(start_page, end_page);
+        let prompt = format!(
+            "Extract all text from pages {start_page} to {end_page} of this PDF document. \
+                Return ONLY the text content from those specific pages, \
+                preserving all Arabic text exactly as it appears. \
+                {} \
+                If these pages don't exist, return an empty response.",
+            page_marker_instruction(start_page)
+        );
+        let text = self
+            .generate_for_range(file_uri, range, prompt, GenerationConfig::text())
+            .await?;
+        pages::split_pages(&text, range)
+    }
+
+    /// Extracts pages `start_page` to `end_page` of an uploaded PDF as
+    /// Markdown, one [`Page`] per requested page.
+    ///
+    /// Headings, lists, footnotes and GFM tables are checked and tidied by
+    /// [`markdown::normalize_page`]; use [`markdown::join_pages`] to merge
+    /// pages without breaking tables or lists that continue on the next.
+    pub async fn extract_markdown_range(
+        &self,
+        file_uri: &str,
+        start_page: usize,
+        end_page: usize,
+    ) -> Result<Vec<Page>> {
+        let range = PageRange::new(start_page, end_page);
+        let prompt = format!(
+            "Convert pages {start_page} to {end_page} of this PDF document to GitHub-flavoured \
+                Markdown, preserving all Arabic text exactly as it appears. \
+                Use # headings for titles and section headings, numbered lists for \
+                enumerations, [^n] references with [^n]: definitions for footnotes, \
+                and pipe tables with a header row and a --- delimiter row for tables, \
+                keeping the cells in reading order and escaping | inside cells as \\|. \
+                Do not wrap the answer in a code block and skip running headers and page numbers. \
+                {}",
+            page_marker_instruction(start_page)
+        );
+        let text = self
+            .generate_for_range(file_uri, range, prompt, GenerationConfig::text())
+            .await?;
+        pages::split_pages(&text, range)?
+            .into_iter()
+            .map(|page| {
+                let text = markdown::normalize_page(&page.text).map_err(|message| {
+                    Error::InvalidMarkdown {
+                        page: page.number,
+                        message,
+                    }
+                })?;
+                Ok(Page { text, ..page })
+            })
+            .collect()
+    }
+
+    /// Extracts pages `start_page` to `end_page` of an uploaded PDF as a
+    /// document model: headings, paragraphs, footnotes, tables and captions
+    /// in reading order, requested through a `responseSchema`.
+    pub async fn extract_structured_range(
+        &self,
+        file_uri: &str,
+        start_page: usize,
+        end_page: usize,
+    ) -> Result<Vec<Page>> {
+        let range = PageRange::new(start_page, end_page);
+        let prompt = format!(
+            "Extract the content of pages {start_page} to {end_page} of this PDF document \
+                as structured blocks, preserving all Arabic text exactly as it appears. \
+                Return one entry per page, where page is the position of the page \
+                in this PDF file, not the page number printed on it. \
+                Classify every block as a heading, paragraph, footnote, table or caption, \
+                number the blocks in reading order (right to left, top to bottom), \
+                give table cells row by row in the order they are read, \
+                and skip running headers and page numbers."
+        );
+        let config = GenerationConfig {
+            response_mime_type: "application/json".to_string(),
+            response_schema: Some(document::response_schema()),
+        };
+        let json = self
+            .generate_for_range(file_uri, range, prompt, config)
+            .await?;
+        document::parse_structured(&json, range)
+    }
+
+    /// Asks about `range` of an uploaded PDF and returns the raw answer.
+    /// The diacritics instruction is appended to `prompt`.
+    async fn generate_for_range(
+        &self,
+        file_uri: &str,
+        range: PageRange,
+        prompt: String,
+        generation_config: GenerationConfig,
     ) -> Result<String> {
         let request = GeminiRequest {
             contents: vec![Content {
                 role: "user".to_string(),
                 parts: vec![
                     Part::Text {
-                        text: format!(
-                            "Extract all text from pages {start_page} to {end_page} of this PDF document. \
-                                Return ONLY the text content from those specific pages, \
-                                preserving all Arabic text exactly as it appears. \
-                                If these pages don't exist, return an empty response."
-                        ),
+                        text: format!("{prompt} {}", self.diacritics.instruction()),
                     },
                     Part::FileData {
                         file_data: FileData {
@@ -277,56 +1002,74 @@ impl GeminiClient {
                     },
                 ],
             }],
-            generation_config: GenerationConfig {
-                response_mime_type: "text/plain".to_string(),
-            },
+            generation_config,
         };
 
-        let url = format!(
-            "{}/v1beta/models/gemini-2.5-flash:generateContent?key={}",
-            self.base_url, self.api_key
-        );
-
-        let response = self
-            .client
-            .post(&url)
-            .json(&request)
-            .timeout(std::time::Duration::from_secs(120))
-            .send()
-            .await
-            .context(format!("Failed to process pages {start_page}-{end_page}"))?;
+        self.generate(
+            &request,
+            self.timeout,
+            &format!("pages {range}"),
+            TOKENS_PER_PAGE * range.len() as u64 + OUTPUT_TOKEN_ESTIMATE,
+        )
+        .await
+    }
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!(
-                "Gemini API error for pages {}-{}: {}",
-                start_page,
-                end_page,
-                error_text
+    /// Sends a `generateContent` request within the rate limits and returns
+    /// the text of the first candidate.
+    async fn generate(
+        &self,
+        request: &GeminiRequest,
+        timeout: Duration,
+        what: &str,
+        fallback_tokens: u64,
+    ) -> Result<String> {
+        async {
+            let url = format!(
+                "{}/{}/models/{}:generateContent",
+                self.base_url, self.api_version, self.model
             );
-        }
 
-        let gemini_response: GeminiResponse = response.json().await.context(format!(
-            "Failed to parse response for pages {start_page}-{end_page}"
-        ))?;
+            let estimated_tokens = self.rate_limiter.estimate_tokens(fallback_tokens);
+            let _permit = self.rate_limiter.acquire(estimated_tokens).await;
 
-        gemini_response
-            .candidates
-            .first()
-            .and_then(|c| c.content.parts.first())
-            .map(|p| p.text.clone())
-            .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "No text found in response for pages {}-{}",
-                    start_page,
-                    end_page
-                )
-            })
+            let response = self
+                .client
+                .post(&url)
+                .header(API_KEY_HEADER, self.api_key_header.clone())
+                .json(request)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(Error::http(format!("Failed to process {what}")))?;
+
+            if !response.status().is_success() {
+                let operation = format!("Gemini API error for {what}");
+                return Err(Error::from_response(operation, response).await);
+            }
+
+            let context = format!("Failed to parse response for {what}");
+            let gemini_response: GeminiResponse = response
+                .json()
+                .await
+                .map_err(|e| Error::parse(&context, e))?;
+
+            if let Some(actual) = gemini_response
+                .usage_metadata
+                .as_ref()
+                .and_then(|usage| usage.total_token_count)
+            {
+                self.rate_limiter.record_usage(estimated_tokens, actual);
+            }
+
+            gemini_response.into_text(&context)
+        }
+        .await
+        .map_err(|e| e.redact(&self.api_key))
     }
 
     pub async fn convert_to_text(&self, pdf_data: &[u8]) -> Result<String> {
         // Upload the file first
-        let file_uri = self.upload_file(pdf_data, "arabic_pdf").await?;
+        let file_uri = self.upload_full_pdf(pdf_data, "arabic_pdf").await?.uri;
 
         // Generate content using the uploaded file
         let request = GeminiRequest {
@@ -346,46 +1089,17 @@ impl GeminiClient {
                     ],
                 },
             ],
-            generation_config: GenerationConfig {
-                response_mime_type: "text/plain".to_string(),
-            },
+            generation_config: GenerationConfig::text(),
         };
 
-        let url = format!(
-            "{}/v1beta/models/gemini-2.5-flash:generateContent?key={}",
-            self.base_url, self.api_key
-        );
-
-        println!("Sending request to generate content...");
-        println!("This may take a while for large PDFs...");
-
-        let response = self
-            .client
-            .post(&url)
-            .json(&request)
-            .timeout(std::time::Duration::from_secs(600)) // 10 minute timeout for generation
-            .send()
-            .await
-            .context("Failed to send request to Gemini API (timeout or network error)")?;
-
-        println!("Response status: {}", response.status());
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("Gemini API error: {}", error_text);
-        }
-
-        let gemini_response: GeminiResponse = response
-            .json()
-            .await
-            .context("Failed to parse Gemini API response")?;
-
-        gemini_response
-            .candidates
-            .first()
-            .and_then(|c| c.content.parts.first())
-            .map(|p| p.text.clone())
-            .ok_or_else(|| anyhow::anyhow!("No text found in Gemini response"))
+        // 10 minute timeout for generation
+        self.generate(
+            &request,
+            self.timeout.max(Duration::from_secs(600)),
+            "document",
+            TOKENS_PER_PAGE * 100 + OUTPUT_TOKEN_ESTIMATE,
+        )
+        .await
     }
 }
 
@@ -394,6 +1108,37 @@ mod tests {
     use super::*;
     use mockito::Server;
 
+    /// Forwards connections to `server`, except the first, which is closed
+    /// as soon as a request arrives on it. Returns the proxy's URL.
+    async fn dropping_proxy(server: &Server) -> String {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let target = server.host_with_port();
+        tokio::spawn(async move {
+            let (mut dropped, _) = listener.accept().await.unwrap();
+            let _ = dropped.read(&mut [0; 64]).await;
+            drop(dropped);
+            while let Ok((mut client, _)) = listener.accept().await {
+                let mut upstream = TcpStream::connect(&target).await.unwrap();
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        format!("http://{address}")
+    }
+
+    fn test_client(base_url: &str) -> GeminiClient {
+        GeminiClient::builder("test_key")
+            .base_url(base_url)
+            .rate_limiter(Arc::new(RateLimiter::new(RateLimits::unlimited())))
+            .build()
+            .unwrap()
+    }
+
     #[tokio::test]
     async fn test_gemini_client_success() {
         let mut server = Server::new_async().await;
@@ -401,7 +1146,8 @@ mod tests {
         // Mock the file upload initiation
         let upload_init_mock = server
             .mock("POST", "/upload/v1beta/files")
-            .match_query(mockito::Matcher::Regex("key=test_key".to_string()))
+            .match_query(mockito::Matcher::Missing)
+            .match_header(API_KEY_HEADER, "test_key")
             .match_header("x-goog-upload-protocol", "resumable")
             .with_status(200)
             .with_header("x-goog-upload-url", &format!("{}/upload/123", server.url()))
@@ -412,14 +1158,14 @@ mod tests {
         let upload_mock = server
             .mock("POST", "/upload/123")
             .with_status(200)
-            .with_body(r#"{"file": {"uri": "https://file-uri/123", "name": "files/123"}}"#)
+            .with_body(r#"{"file": {"uri": "https://file-uri/123", "name": "files/123", "state": "ACTIVE"}}"#)
             .create_async()
             .await;
 
         // Mock the generate content call
         let generate_mock = server
             .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
-            .match_query(mockito::Matcher::Regex("key=test_key".to_string()))
+            .match_header(API_KEY_HEADER, "test_key")
             .with_status(200)
             .with_body(
                 r#"{
@@ -433,7 +1179,7 @@ mod tests {
             .create_async()
             .await;
 
-        let client = GeminiClient::with_base_url("test_key", &server.url());
+        let client = test_client(&server.url());
 
         let test_pdf = b"test pdf data";
         let result = client.convert_to_text(test_pdf).await;
@@ -453,18 +1199,549 @@ mod tests {
         // Mock upload initiation failure
         let upload_init_mock = server
             .mock("POST", "/upload/v1beta/files")
-            .match_query(mockito::Matcher::Regex("key=test_key".to_string()))
+            .match_header(API_KEY_HEADER, "test_key")
             .with_status(400)
             .with_body("Invalid API key")
             .create_async()
             .await;
 
-        let client = GeminiClient::with_base_url("test_key", &server.url());
+        let client = test_client(&server.url());
 
         let test_pdf = b"test pdf data";
         let result = client.convert_to_text(test_pdf).await;
 
         upload_init_mock.assert_async().await;
-        assert!(result.is_err());
+        assert!(matches!(result, Err(Error::UploadFailed { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_api_key_is_auth_error() {
+        let mut server = Server::new_async().await;
+        let generate_mock = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(400)
+            .with_body(
+                r#"{"error": {
+                    "code": 400,
+                    "message": "API key test_key not valid. Please pass a valid API key.",
+                    "status": "INVALID_ARGUMENT",
+                    "details": [{"@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "API_KEY_INVALID"}]
+                }}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = test_client(&server.url());
+        let result = client
+            .extract_page_range("https://file-uri/123", 1, 5)
+            .await;
+
+        generate_mock.assert_async().await;
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::Auth { .. }));
+        assert_eq!(
+            error.api_details().unwrap().status.as_deref(),
+            Some("INVALID_ARGUMENT")
+        );
+        assert!(!error.to_string().contains("test_key"));
+    }
+
+    #[tokio::test]
+    async fn test_safety_block_is_reported() {
+        let mut server = Server::new_async().await;
+        let generate_mock = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"candidates": [{"finishReason": "SAFETY"}]}"#)
+            .create_async()
+            .await;
+
+        let client = test_client(&server.url());
+        let result = client
+            .extract_page_range("https://file-uri/123", 1, 5)
+            .await;
+
+        generate_mock.assert_async().await;
+        assert!(matches!(result, Err(Error::SafetyBlock { reason }) if reason == "SAFETY"));
+    }
+
+    #[test]
+    fn test_backoff_is_capped_and_jittered() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(10),
+        };
+        for attempt in 1..=10 {
+            let delay = policy.backoff(attempt);
+            let capped = Duration::from_secs(2 << (attempt - 1).min(4)).min(policy.max_backoff);
+            assert!(
+                delay >= capped / 2 && delay <= capped,
+                "attempt {attempt}: {delay:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_retries_rate_limits() {
+        let mut server = Server::new_async().await;
+        let rate_limited = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .with_body(r#"{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let success = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"{"candidates": [{"content": {"parts": [{"text": "<<<PAGE 1>>>\nنص"}]}}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = test_client(&server.url());
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        };
+        let mut retries = 0;
+        let result = policy
+            .run(
+                || client.extract_page_range("https://file-uri/123", 1, 5),
+                |_, _, _| retries += 1,
+            )
+            .await;
+
+        rate_limited.assert_async().await;
+        success.assert_async().await;
+        let pages = result.unwrap();
+        assert_eq!(pages.len(), 5);
+        assert_eq!(pages[0].text, "نص");
+        assert_eq!(retries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_retries_uploads_after_dropped_connection() {
+        let mut server = Server::new_async().await;
+        let init = server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/123", server.url()))
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/123")
+            .with_status(200)
+            .with_body(r#"{"file": {"uri": "https://file-uri/123", "name": "files/123", "state": "ACTIVE"}}"#)
+            .create_async()
+            .await;
+
+        // The first attempt loses its connection before any response
+        let client = test_client(&dropping_proxy(&server).await);
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        };
+        let mut retries = 0;
+        let result = policy
+            .run(
+                || client.upload_with_progress(b"%PDF", "test", Arc::new(|_| {})),
+                |_, _, _| retries += 1,
+            )
+            .await;
+
+        init.assert_async().await;
+        assert_eq!(result.unwrap().name, "files/123");
+        assert_eq!(retries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_does_not_retry_client_errors() {
+        let mut server = Server::new_async().await;
+        let bad_request = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(400)
+            .with_body("Invalid argument")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = test_client(&server.url());
+        let policy = RetryPolicy::default();
+        let result = policy
+            .run(
+                || client.extract_page_range("https://file-uri/123", 1, 5),
+                |_, _, _| panic!("client errors must not be retried"),
+            )
+            .await;
+
+        bad_request.assert_async().await;
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::Api { .. }));
+        assert_eq!(error.status(), Some(reqwest::StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_builder_model_and_api_version() {
+        let mut server = Server::new_async().await;
+        let generate_mock = server
+            .mock("POST", "/v1/models/gemini-2.5-pro:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"candidates": [{"content": {"parts": [{"text": "نص"}]}}]}"#)
+            .create_async()
+            .await;
+
+        let client = GeminiClient::builder("test_key")
+            .base_url(&format!("{}/", server.url()))
+            .api_version("v1")
+            .model("models/gemini-2.5-pro")
+            .timeout(Duration::from_secs(5))
+            .http_client(Client::new())
+            .build()
+            .unwrap();
+        let result = client
+            .extract_page_range("https://file-uri/123", 1, 1)
+            .await;
+
+        generate_mock.assert_async().await;
+        assert_eq!(client.model(), "gemini-2.5-pro");
+        assert_eq!(result.unwrap(), [Page::new(1, "نص")]);
+    }
+
+    #[tokio::test]
+    async fn test_structured_extraction_sends_response_schema() {
+        let mut server = Server::new_async().await;
+        let generate_mock = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "generationConfig": {
+                    "responseMimeType": "application/json",
+                    "responseSchema": {"required": ["pages"]}
+                }
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{"candidates": [{"content": {"parts": [{"text":
+                    "{\"pages\": [{\"page\": 1, \"blocks\": [{\"order\": 0, \"type\": \"paragraph\", \"text\": \"نص\"}]}]}"
+                }]}}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = test_client(&server.url());
+        let pages = client
+            .extract_structured_range("https://file-uri/123", 1, 2)
+            .await
+            .unwrap();
+
+        generate_mock.assert_async().await;
+        assert_eq!(
+            pages[0].blocks,
+            [document::Block::Paragraph {
+                text: "نص".to_string()
+            }]
+        );
+        assert_eq!(pages[0].text, "نص");
+        assert!(pages[1].blocks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upload_waits_until_active() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/123", server.url()))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/123")
+            .with_status(200)
+            .with_body(r#"{"file": {"uri": "https://file-uri/123", "name": "files/123", "state": "PROCESSING"}}"#)
+            .create_async()
+            .await;
+        let processing = server
+            .mock("GET", "/v1beta/files/123")
+            .match_header(API_KEY_HEADER, "test_key")
+            .with_status(200)
+            .with_body(
+                r#"{"uri": "https://file-uri/123", "name": "files/123", "state": "PROCESSING"}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let active = server
+            .mock("GET", "/v1beta/files/123")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "name": "files/123",
+                    "uri": "https://file-uri/123",
+                    "mimeType": "application/pdf",
+                    "sizeBytes": "13",
+                    "sha256Hash": "ZGVhZGJlZWY=",
+                    "expirationTime": "2030-01-03T00:00:00Z",
+                    "state": "ACTIVE"
+                }"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::builder("test_key")
+            .base_url(&server.url())
+            .poll_interval(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let file = client
+            .upload_full_pdf(b"test pdf data", "test")
+            .await
+            .unwrap();
+
+        processing.assert_async().await;
+        active.assert_async().await;
+        assert_eq!(file.state, FileState::Active);
+        assert_eq!(file.size_bytes, Some(13));
+        assert_eq!(file.mime_type.as_deref(), Some("application/pdf"));
+        assert!(file.expiration_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_processing_is_reported() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/v1beta/files/123")
+            .with_status(200)
+            .with_body(
+                r#"{"name": "files/123", "uri": "u", "state": "FAILED",
+                    "error": {"code": 3, "message": "Unsupported PDF"}}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = GeminiClient::builder("test_key")
+            .base_url(&server.url())
+            .poll_interval(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let pending = UploadedFile {
+            name: "files/123".to_string(),
+            state: FileState::Processing,
+            ..UploadedFile::default()
+        };
+        let error = client.wait_until_active(pending.clone()).await.unwrap_err();
+        assert!(
+            matches!(error, Error::FileFailed { ref message, .. } if message == "Unsupported PDF")
+        );
+
+        let impatient = GeminiClient::builder("test_key")
+            .base_url(&server.url())
+            .activation_timeout(Duration::ZERO)
+            .build()
+            .unwrap();
+        let error = impatient.wait_until_active(pending).await.unwrap_err();
+        assert!(matches!(error, Error::FileNotActive { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delete_files() {
+        let mut server = Server::new_async().await;
+        let first_page = server
+            .mock("GET", "/v1beta/files")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "pageSize".to_string(),
+                "100".to_string(),
+            ))
+            .match_header(API_KEY_HEADER, "test_key")
+            .with_status(200)
+            .with_body(
+                r#"{"files": [{"name": "files/1", "uri": "u1", "state": "ACTIVE"}],
+                    "nextPageToken": "next"}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let second_page = server
+            .mock("GET", "/v1beta/files")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "pageToken".to_string(),
+                "next".to_string(),
+            ))
+            .with_status(200)
+            .with_body(r#"{"files": [{"name": "files/2", "uri": "u2"}]}"#)
+            .create_async()
+            .await;
+        let delete = server
+            .mock("DELETE", "/v1beta/files/1")
+            .match_header(API_KEY_HEADER, "test_key")
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let client = test_client(&server.url());
+        let files = client.list_files().await.unwrap();
+        client.delete_file("files/1").await.unwrap();
+
+        first_page.assert_async().await;
+        second_page.assert_async().await;
+        delete.assert_async().await;
+        let names: Vec<_> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, ["files/1", "files/2"]);
+    }
+
+    #[test]
+    fn test_upload_chunk_size_is_rounded_up() {
+        let chunk_size = |bytes| {
+            GeminiClient::builder("test_key")
+                .upload_chunk_size(bytes)
+                .upload_chunk_size
+        };
+        assert_eq!(chunk_size(0), 256 * 1024);
+        assert_eq!(chunk_size(5 * 1024 * 1024), 5 * 1024 * 1024);
+        assert_eq!(
+            chunk_size(5 * 1024 * 1024 + 1),
+            5 * 1024 * 1024 + 256 * 1024
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload_resumes_after_dropped_connection() {
+        let mut server = Server::new_async().await;
+        let proxy = dropping_proxy(&server).await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{proxy}/upload/123"))
+            .create_async()
+            .await;
+        let query = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "query")
+            .with_status(200)
+            .with_header("x-goog-upload-size-received", "0")
+            .with_header("x-goog-upload-status", "active")
+            .expect(1)
+            .create_async()
+            .await;
+        let first = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "upload")
+            .match_header("x-goog-upload-offset", "0")
+            .match_body("0123")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let last = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "upload, finalize")
+            .match_header("x-goog-upload-offset", "4")
+            .match_body("456")
+            .with_status(200)
+            .with_body(r#"{"file": {"uri": "https://file-uri/123", "name": "files/123", "state": "ACTIVE"}}"#)
+            .create_async()
+            .await;
+
+        let mut builder = GeminiClient::builder("test_key").base_url(&server.url());
+        builder.upload_chunk_size = 4;
+        let client = builder.build().unwrap();
+        let file = client
+            .upload_with_progress(b"0123456", "test", Arc::new(|_| {}))
+            .await
+            .unwrap();
+
+        query.assert_async().await;
+        first.assert_async().await;
+        last.assert_async().await;
+        assert_eq!(file.name, "files/123");
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload_resumes_from_acknowledged_offset() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/123", server.url()))
+            .create_async()
+            .await;
+        let first = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "upload")
+            .match_header("x-goog-upload-offset", "0")
+            .match_body("0123")
+            .with_status(200)
+            .create_async()
+            .await;
+        let dropped = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "upload")
+            .match_header("x-goog-upload-offset", "4")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let query = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "query")
+            .with_status(200)
+            .with_header("x-goog-upload-size-received", "4")
+            .with_header("x-goog-upload-status", "active")
+            .create_async()
+            .await;
+        let resumed = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "upload")
+            .match_header("x-goog-upload-offset", "4")
+            .match_body("4567")
+            .with_status(200)
+            .create_async()
+            .await;
+        let last = server
+            .mock("POST", "/upload/123")
+            .match_header("x-goog-upload-command", "upload, finalize")
+            .match_header("x-goog-upload-offset", "8")
+            .match_body("89")
+            .with_status(200)
+            .with_body(r#"{"file": {"uri": "https://file-uri/123", "name": "files/123", "state": "ACTIVE"}}"#)
+            .create_async()
+            .await;
+
+        // Smaller than the builder allows, to keep the payload short
+        let mut builder = GeminiClient::builder("test_key").base_url(&server.url());
+        builder.upload_chunk_size = 4;
+        let client = builder.build().unwrap();
+        let sent = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let progress_sent = sent.clone();
+        let file = client
+            .upload_with_progress(
+                b"0123456789",
+                "test",
+                Arc::new(move |bytes| {
+                    progress_sent.store(bytes, std::sync::atomic::Ordering::SeqCst)
+                }),
+            )
+            .await
+            .unwrap();
+
+        first.assert_async().await;
+        dropped.assert_async().await;
+        query.assert_async().await;
+        resumed.assert_async().await;
+        last.assert_async().await;
+        assert_eq!(file.name, "files/123");
+        assert_eq!(sent.load(std::sync::atomic::Ordering::SeqCst), 10);
     }
 }
@@ -0,0 +1,235 @@
+use crate::document::OutputFormat;
+use crate::error::{Error, Result};
+use crate::gemini_client::UploadedFile;
+use crate::pages::{Page, PageRange};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Key under which the upload of the whole document is recorded.
+pub const WHOLE_DOCUMENT: &str = "document";
+
+/// Uploads expiring sooner than this are not reused.
+const EXPIRY_MARGIN_MINUTES: i64 = 10;
+
+/// Checkpoint of an extraction job, stored as JSON per input PDF.
+///
+/// The state is written after every completed page range so that a rerun
+/// with `--resume` only requests the ranges that are still missing.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct JobState {
+    pub pdf_sha256: String,
+    /// Uploaded files keyed by [`WHOLE_DOCUMENT`] or by page range.
+    pub uploads: BTreeMap<String, UploadRecord>,
+    /// Extracted pages keyed by page range, e.g. `"6-10"`. Checkpoints
+    /// written before per-page output kept plain text under `completed`,
+    /// which is ignored, so those ranges are extracted again.
+    #[serde(default)]
+    pub pages: BTreeMap<String, Vec<Page>>,
+    /// Format in which `pages` were extracted.
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadRecord {
+    pub name: String,
+    pub uri: String,
+    pub expiration_time: Option<DateTime<Utc>>,
+}
+
+impl From<&UploadedFile> for UploadRecord {
+    fn from(file: &UploadedFile) -> Self {
+        Self {
+            name: file.name.clone(),
+            uri: file.uri.clone(),
+            expiration_time: file.expiration_time,
+        }
+    }
+}
+
+impl UploadRecord {
+    /// Whether the remote file can still be used for a while.
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.expiration_time
+            .is_some_and(|expires| expires - Duration::minutes(EXPIRY_MARGIN_MINUTES) > now)
+    }
+}
+
+impl JobState {
+    /// Starts an empty job for the given document.
+    pub fn new(state_dir: &Path, pdf_sha256: &str) -> Self {
+        Self {
+            pdf_sha256: pdf_sha256.to_string(),
+            path: state_dir.join(format!("{pdf_sha256}.json")),
+            ..Self::default()
+        }
+    }
+
+    /// Loads the checkpoint for the given document, or starts an empty job
+    /// when none exists yet.
+    pub fn load_or_new(state_dir: &Path, pdf_sha256: &str) -> Result<Self> {
+        let mut state = Self::new(state_dir, pdf_sha256);
+        if !state.path.exists() {
+            return Ok(state);
+        }
+
+        let json =
+            fs::read_to_string(&state.path).map_err(Error::io("Failed to read job state"))?;
+        let saved: Self = serde_json::from_str(&json)
+            .map_err(|e| Error::parse("Failed to parse job state", e))?;
+        if saved.pdf_sha256 == pdf_sha256 {
+            state.uploads = saved.uploads;
+            state.pages = saved.pages;
+            state.format = saved.format;
+        }
+        Ok(state)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a recorded upload that has not expired yet.
+    pub fn upload(&self, key: &str) -> Option<&UploadRecord> {
+        self.uploads
+            .get(key)
+            .filter(|upload| upload.is_usable(Utc::now()))
+    }
+
+    pub fn record_upload(&mut self, key: &str, file: &UploadedFile) {
+        self.uploads.insert(key.to_string(), file.into());
+    }
+
+    /// Switches the job to `format`, dropping pages extracted in another
+    /// format so that they are extracted again.
+    pub fn set_format(&mut self, format: OutputFormat) {
+        if self.format != format {
+            self.pages.clear();
+            self.format = format;
+        }
+    }
+
+    pub fn completed_pages(&self, range: PageRange) -> Option<&[Page]> {
+        self.pages.get(&range.to_string()).map(Vec::as_slice)
+    }
+
+    pub fn record_range(&mut self, range: PageRange, pages: &[Page]) {
+        self.pages.insert(range.to_string(), pages.to_vec());
+    }
+
+    /// Writes the checkpoint atomically so a crash never leaves it truncated.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(Error::io("Failed to create job state directory"))?;
+        }
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| Error::parse("Failed to serialize job state", e))?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json).map_err(Error::io("Failed to write job state"))?;
+        fs::rename(&tmp_path, &self.path).map_err(Error::io("Failed to write job state"))?;
+        Ok(())
+    }
+
+    /// Deletes the checkpoint once the job has finished.
+    pub fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.map_err(Error::io("Failed to remove job state")),
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of the document, used to identify jobs.
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Default location for job checkpoints and other cached state.
+pub fn default_state_dir() -> PathBuf {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    cache_dir.join("arabic_pdf_to_text").join("jobs")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn uploaded(expires_in_hours: i64) -> UploadedFile {
+        UploadedFile {
+            name: "files/123".to_string(),
+            uri: "https://file-uri/123".to_string(),
+            expiration_time: Some(Utc::now() + Duration::hours(expires_in_hours)),
+            ..UploadedFile::default()
+        }
+    }
+
+    #[test]
+    fn test_job_state_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let mut state = JobState::new(dir.path(), "abc");
+        state.record_upload(WHOLE_DOCUMENT, &uploaded(48));
+        let pages = [Page::new(1, "نص الصفحة")];
+        state.record_range(PageRange::new(1, 1), &pages);
+        state.save().unwrap();
+
+        let loaded = JobState::load_or_new(dir.path(), "abc").unwrap();
+        assert_eq!(
+            loaded.completed_pages(PageRange::new(1, 1)),
+            Some(&pages[..])
+        );
+        assert_eq!(loaded.completed_pages(PageRange::new(2, 5)), None);
+        assert_eq!(
+            loaded.upload(WHOLE_DOCUMENT).unwrap().uri,
+            "https://file-uri/123"
+        );
+    }
+
+    #[test]
+    fn test_expired_upload_is_not_reused() {
+        let dir = TempDir::new().unwrap();
+        let mut state = JobState::new(dir.path(), "abc");
+        state.record_upload(WHOLE_DOCUMENT, &uploaded(-1));
+        assert!(state.upload(WHOLE_DOCUMENT).is_none());
+    }
+
+    #[test]
+    fn test_missing_state_starts_empty() {
+        let dir = TempDir::new().unwrap();
+        let state = JobState::load_or_new(dir.path(), "abc").unwrap();
+        assert!(state.pages.is_empty());
+        state.remove().unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_without_pages_is_loaded() {
+        let dir = TempDir::new().unwrap();
+        let old = r#"{
+            "pdf_sha256": "abc",
+            "uploads": {},
+            "completed": {"1-5": "نص قديم"}
+        }"#;
+        fs::write(dir.path().join("abc.json"), old).unwrap();
+
+        let state = JobState::load_or_new(dir.path(), "abc").unwrap();
+        assert!(state.pages.is_empty());
+        assert_eq!(state.completed_pages(PageRange::new(1, 5)), None);
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,143 +1,428 @@
+pub mod bidi;
 pub mod chunker;
+pub mod credentials;
+pub mod document;
+pub mod error;
 pub mod gemini_client;
+pub mod job;
+pub mod markdown;
+pub mod normalize;
+pub mod pages;
 pub mod pdf_reader;
+pub mod rate_limit;
+pub mod report;
+pub mod text_layer;
 pub mod ui;
-
-use crate::ui::VerboseUI;
-use anyhow::Result;
+pub mod upload_cache;
+pub mod verify;
+
+use crate::bidi::{BidiMode, ReversedLines};
+use crate::document::OutputFormat;
+pub use crate::error::{Error, Result};
+use crate::gemini_client::{GeminiClient, RetryPolicy, UploadProgress, UploadedFile};
+use crate::job::JobState;
+use crate::normalize::{Diacritics, HarakatCount, Normalization};
+use crate::pages::{Page, PageRange, PageSelection};
+use crate::pdf_reader::PdfSource;
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::report::{FailedRange, JobSummary, NoProgress, ProgressSink};
+use crate::upload_cache::UploadCache;
+use crate::verify::PageVerification;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// What happens to page ranges that still fail after all retries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Fail the job with [`Error::RangesFailed`].
+    #[default]
+    Fail,
+    /// Put a [`Page::placeholder`] in place of every missing page.
+    Placeholder,
+    /// Leave the missing pages out.
+    Skip,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "fail" => Ok(Self::Fail),
+            "placeholder" => Ok(Self::Placeholder),
+            "skip" => Ok(Self::Skip),
+            other => Err(Error::InvalidInput(format!(
+                "unknown failure policy '{other}' (expected fail, placeholder or skip)"
+            ))),
+        }
+    }
+}
+
+/// The result of [`process_pdf`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extraction {
+    /// Extracted pages in page order, including placeholders for missing
+    /// pages under [`FailurePolicy::Placeholder`].
+    pub pages: Vec<Page>,
+    /// Ranges that failed; empty when the extraction is complete.
+    pub failed: Vec<FailedRange>,
+    /// Pages checked against their text layer, with [`Config::verify`].
+    pub verification: Vec<PageVerification>,
+}
+
+impl Extraction {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
 pub struct Config {
     pub api_key: String,
+    /// Largest PDF, in bytes, uploaded as a single file. Bigger documents
+    /// and partial page selections are uploaded as per-range sub-PDFs.
     pub chunk_size: usize,
+    /// Pages to extract; `None` extracts the whole document.
+    pub pages: Option<PageSelection>,
+    /// Maximum number of pages sent in a single extraction request.
+    pub pages_per_chunk: usize,
+    /// Continue from the checkpoint left by an interrupted run.
+    pub resume: bool,
+    /// Directory holding per-document job checkpoints.
+    pub state_dir: PathBuf,
+    /// Backoff applied to failed uploads and extraction requests.
+    pub retry: RetryPolicy,
+    /// Concurrency, requests-per-minute and tokens-per-minute budgets.
+    pub rate_limits: RateLimits,
+    /// Gemini model used for extraction, e.g. `gemini-2.5-pro`.
+    pub model: String,
+    /// API version segment of the request URLs, e.g. `v1beta`.
+    pub api_version: String,
+    /// Alternative API root such as a proxy; `None` uses Google's endpoint.
+    pub endpoint: Option<String>,
+    /// Delete the job's uploaded files once every page range succeeded,
+    /// except those the upload cache keeps for later runs.
+    pub cleanup_uploads: bool,
+    /// Reuse uploads of identical content across runs, see [`UploadCache`].
+    /// Cached uploads outlive [`Self::cleanup_uploads`] until Gemini
+    /// expires them.
+    pub upload_cache: bool,
+    /// What to do when page ranges fail; failing the job by default.
+    pub on_failure: FailurePolicy,
+    /// Plain text, Markdown or a document model per page.
+    pub format: OutputFormat,
+    /// Take plain-text pages whose embedded text layer is usable from the
+    /// PDF itself, sending only scanned or broken pages to Gemini. On by
+    /// default; Markdown and JSON always go through Gemini.
+    pub text_layer: bool,
+    /// Checks pages extracted by Gemini against their text layer and flags
+    /// those whose character error rate exceeds this threshold; off by
+    /// default. Every page is then sent to Gemini, see [`verify`].
+    pub verify: Option<f64>,
+    /// Whether lines that read backwards are only reported or also
+    /// repaired; reported by default.
+    pub bidi: BidiMode,
+    /// Whether diacritics are kept, asked for in the prompt and enforced
+    /// locally; kept as printed by default.
+    pub diacritics: Diacritics,
+    /// Post-processing of the extracted text; none by default. Checkpoints
+    /// keep the text as extracted, so a resumed job may normalize
+    /// differently.
+    pub normalize: Normalization,
+    /// Receives upload, page range and job events. Defaults to
+    /// [`NoProgress`], so the library prints nothing; the CLI passes
+    /// [`ui::VerboseUI`] or another sink chosen by `--log-format`.
+    pub progress: Arc<dyn ProgressSink>,
+}
+
+impl Config {
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            chunk_size: 50 * 1024 * 1024,
+            pages: None,
+            pages_per_chunk: 5,
+            resume: false,
+            state_dir: job::default_state_dir(),
+            retry: RetryPolicy::default(),
+            rate_limits: RateLimits::default(),
+            model: gemini_client::DEFAULT_MODEL.to_string(),
+            api_version: gemini_client::DEFAULT_API_VERSION.to_string(),
+            endpoint: None,
+            cleanup_uploads: true,
+            upload_cache: true,
+            on_failure: FailurePolicy::default(),
+            format: OutputFormat::default(),
+            text_layer: true,
+            verify: None,
+            bidi: BidiMode::default(),
+            diacritics: Diacritics::default(),
+            normalize: Normalization::default(),
+            progress: Arc::new(NoProgress),
+        }
+    }
 }
 
-pub async fn process_pdf(path: &str, config: &Config) -> Result<String> {
-    let ui = VerboseUI::new();
-    ui.print_banner();
+/// Extracts the selected pages of the PDF at `path`, in page order.
+///
+/// Page ranges that still fail after all retries are handled according to
+/// [`Config::on_failure`] and can be retried later with [`Config::resume`].
+pub async fn process_pdf(path: &str, config: &Config) -> Result<Extraction> {
+    let ui = config.progress.clone();
 
-    // Read PDF with detailed feedback
-    ui.print_file_info(path, 0); // We'll get size after reading
     let pdf_data = pdf_reader::read_pdf(path)?;
     let total_size = pdf_data.len();
-    ui.print_file_info(path, total_size);
+    ui.job_started(path, total_size as u64);
+
+    // Count pages locally so the page-range plan matches the document
+    let document = pdf_reader::load_document(&pdf_data)?;
+    let total_pages = pdf_reader::page_count(&document)?;
+    ui.page_count(total_pages);
+    let mut ranges: Vec<PageRange> =
+        pages::plan_ranges(config.pages.as_ref(), total_pages, config.pages_per_chunk)?;
+
+    // Read the usable text layers of the selected pages. They replace OCR
+    // for plain text, or are the reference that Gemini's pages are checked
+    // against in verification mode.
+    let read_locally =
+        config.text_layer && config.format == OutputFormat::Text && config.verify.is_none();
+    let mut text_layers = BTreeMap::new();
+    if read_locally || config.verify.is_some() {
+        for number in ranges.iter().flat_map(PageRange::pages) {
+            let layer = if read_locally {
+                text_layer::usable_page(&document, number).ok()
+            } else {
+                text_layer::extract_page(&document, number)
+                    .ok()
+                    .filter(|text| text_layer::check_usable(text).is_ok())
+            };
+            if let Some(text) = layer {
+                text_layers.insert(number, text);
+            }
+        }
+    }
+    let mut local_pages = Vec::new();
+    if read_locally {
+        let needs_ocr: Vec<usize> = ranges
+            .iter()
+            .flat_map(PageRange::pages)
+            .filter(|number| !text_layers.contains_key(number))
+            .collect();
+        ui.text_layer_checked(text_layers.len(), needs_ocr.len());
+        ranges = pages::plan_pages(&needs_ocr, config.pages_per_chunk)?;
+        local_pages = std::mem::take(&mut text_layers)
+            .into_iter()
+            .map(|(number, text)| Page::new(number, text))
+            .collect();
+    }
 
-    // Upload the full PDF once with progress tracking
-    ui.print_upload_start();
-    let client = gemini_client::GeminiClient::new(&config.api_key);
+    // Load the checkpoint of an interrupted run, or start a fresh job
+    let pdf_sha256 = job::sha256_hex(&pdf_data);
+    let mut job = if config.resume {
+        JobState::load_or_new(&config.state_dir, &pdf_sha256)?
+    } else {
+        JobState::new(&config.state_dir, &pdf_sha256)
+    };
+    job.set_format(config.format);
+    let pending: Vec<usize> = (0..ranges.len())
+        .filter(|&index| job.completed_pages(ranges[index]).is_none())
+        .collect();
+    if config.resume {
+        ui.resume_status(ranges.len() - pending.len(), ranges.len());
+    }
 
-    // Create upload progress bar
-    let upload_pb = ui.create_upload_progress(total_size as u64);
+    // Upload the whole document when it is small enough and fully selected,
+    // otherwise upload one self-contained sub-PDF per page range. Uploads
+    // recorded by a previous run are reused until they expire.
+    let selected_pages: usize = ranges.iter().map(PageRange::len).sum();
+    let upload_whole = selected_pages == total_pages && total_size <= config.chunk_size;
+    // URIs of the per-range uploads, taken from the job as they are
+    // checked so that expiry cannot catch up with them later
+    let mut range_uris: BTreeMap<usize, String> = BTreeMap::new();
+    let mut parts = Vec::new();
+    if !upload_whole {
+        for &index in &pending {
+            match job.upload(&ranges[index].to_string()) {
+                Some(upload) => {
+                    range_uris.insert(index, upload.uri.clone());
+                }
+                None => parts.push(index),
+            }
+        }
+    }
+    let whole_needed =
+        upload_whole && !pending.is_empty() && job.upload(job::WHOLE_DOCUMENT).is_none();
+    let upload_size = if whole_needed {
+        total_size
+    } else {
+        // Sub-PDFs are only built right before their upload, so that one
+        // at a time is held in memory; the progress total is estimated
+        let mut size = 0;
+        for &index in &parts {
+            size += chunker::estimated_size(&document, ranges[index])?;
+        }
+        size
+    };
+
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limits.clone()));
+    let mut builder = gemini_client::GeminiClient::builder(&config.api_key)
+        .model(&config.model)
+        .api_version(&config.api_version)
+        .rate_limiter(rate_limiter)
+        .diacritics(config.diacritics);
+    if let Some(endpoint) = &config.endpoint {
+        builder = builder.base_url(endpoint);
+    }
+    let client = builder.build()?;
+    if upload_size > 0 {
+        ui.upload_started(upload_size as u64);
+    }
 
-    // Simulate upload progress (in real implementation, you'd update this during actual upload)
-    tokio::spawn(async move {
-        for i in 0..=100 {
-            upload_pb.set_position((total_size as u64 * i / 100) as u64);
-            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    // Each range is extracted from an uploaded file, using page numbers local to that file
+    let mut upload_cache = if config.upload_cache {
+        Some(UploadCache::load(&config.state_dir)?)
+    } else {
+        None
+    };
+    let mut uploaded_bytes = 0;
+    let mut targets: Vec<Option<(Arc<String>, PageRange)>> = vec![None; ranges.len()];
+    if upload_whole && !pending.is_empty() {
+        let file_uri = match job.upload(job::WHOLE_DOCUMENT) {
+            Some(upload) => upload.uri.clone(),
+            None => {
+                let file = upload(
+                    &client,
+                    config,
+                    &ui,
+                    upload_cache.as_mut(),
+                    &mut uploaded_bytes,
+                    UploadSource::File(&pdf_data, &pdf_sha256),
+                    "arabic_pdf",
+                )
+                .await?;
+                job.record_upload(job::WHOLE_DOCUMENT, &file);
+                job.save()?;
+                file.uri
+            }
+        };
+        let file_uri = Arc::new(file_uri);
+        for &index in &pending {
+            targets[index] = Some((file_uri.clone(), ranges[index]));
         }
-        upload_pb.finish();
-    });
+    } else {
+        for index in parts {
+            let key = ranges[index].to_string();
+            let display_name = format!("arabic_pdf_pages_{key}");
+            let part = chunker::extract_pages(&document, ranges[index])?;
+            let file = upload(
+                &client,
+                config,
+                &ui,
+                upload_cache.as_mut(),
+                &mut uploaded_bytes,
+                UploadSource::Memory(&part),
+                &display_name,
+            )
+            .await?;
+            job.record_upload(&key, &file);
+            job.save()?;
+            range_uris.insert(index, file.uri);
+        }
+        for (index, uri) in range_uris {
+            let local_range = PageRange::new(1, ranges[index].len());
+            targets[index] = Some((Arc::new(uri), local_range));
+        }
+    }
 
-    let file_uri = client.upload_full_pdf(&pdf_data, "arabic_pdf").await?;
-    ui.print_upload_complete(&file_uri);
+    drop(document);
+    if upload_size > 0 {
+        ui.uploads_finished();
+    }
 
     // Process in page ranges
-    let pages_per_chunk = 5; // Process 5 pages at a time
-    let total_pages: usize = 30; // Start with first 30 pages for testing
-    #[allow(clippy::manual_div_ceil)]
-    let num_chunks = (total_pages + pages_per_chunk - 1) / pages_per_chunk;
-
-    ui.print_processing_start(num_chunks, pages_per_chunk);
+    let num_chunks = ranges.len();
+    ui.processing_started(
+        &ranges,
+        config.pages_per_chunk,
+        client.model(),
+        &config.rate_limits,
+    );
 
     // Create shared resources
     let client = Arc::new(client);
-    let file_uri = Arc::new(file_uri);
-    let ui = Arc::new(ui);
-
-    // Create progress bars for each chunk
-    let progress_bars: Vec<_> = (0..num_chunks)
-        .map(|i| {
-            let start = i * pages_per_chunk + 1;
-            let end = ((i + 1) * pages_per_chunk).min(total_pages);
-            Arc::new(Mutex::new(ui.create_chunk_progress(start, end)))
-        })
-        .collect();
-
-    // Process page ranges concurrently (MapReduce pattern)
-    // Limit to 2 concurrent requests to avoid rate limits
-    let semaphore = Arc::new(tokio::sync::Semaphore::new(2));
+    let job = Arc::new(Mutex::new(job));
 
-    let tasks: Vec<_> = (0..num_chunks)
-        .map(|chunk_idx| {
+    // Process page ranges concurrently (MapReduce pattern); the client's
+    // rate limiter decides how many requests are in flight at once
+    let tasks: Vec<_> = pending
+        .iter()
+        .map(|&chunk_idx| {
             let client = client.clone();
-            let sem = semaphore.clone();
-            let file_uri = file_uri.clone();
-            let start_page = chunk_idx * pages_per_chunk + 1;
-            let end_page = ((chunk_idx + 1) * pages_per_chunk).min(total_pages);
-
-            let pb = progress_bars[chunk_idx].clone();
+            let job = job.clone();
+            let retry = config.retry.clone();
+            let format = config.format;
+            let range = ranges[chunk_idx];
+            let (file_uri, local_range) = targets[chunk_idx].clone().unwrap();
             let ui = ui.clone();
 
             tokio::spawn(async move {
-                let _permit = sem.acquire().await.unwrap();
-
-                // Update progress to processing
-                {
-                    let pb_lock = pb.lock().await;
-                    ui.update_chunk_progress(&pb_lock, "processing", 20);
-                }
-
-                // Add delay to respect rate limits
-                if chunk_idx > 0 {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(6)).await;
-                }
-
-                // Simulate some processing time
-                {
-                    let pb_lock = pb.lock().await;
-                    ui.update_chunk_progress(&pb_lock, "processing", 50);
-                }
-
-                let mut retries = 3;
-                let mut result = client
-                    .extract_page_range(&file_uri, start_page, end_page)
-                    .await;
-
-                // Retry on rate limit errors
-                while retries > 0 && result.is_err() {
-                    if let Err(e) = &result {
-                        if e.to_string().contains("RESOURCE_EXHAUSTED") {
-                            {
-                                let pb_lock = pb.lock().await;
-                                ui.update_chunk_progress(&pb_lock, "rate_limited", 70);
+                ui.range_started(range);
+
+                // Retry rate limits, server errors and timeouts with backoff
+                let result = retry
+                    .run(
+                        || async {
+                            let PageRange { start, end } = local_range;
+                            match format {
+                                OutputFormat::Text => {
+                                    client.extract_page_range(&file_uri, start, end).await
+                                }
+                                OutputFormat::Json => {
+                                    client.extract_structured_range(&file_uri, start, end).await
+                                }
+                                OutputFormat::Markdown => {
+                                    client.extract_markdown_range(&file_uri, start, end).await
+                                }
                             }
-                            ui.print_rate_limit_warning(chunk_idx + 1, 30);
-                            retries -= 1;
-                            result = client
-                                .extract_page_range(&file_uri, start_page, end_page)
-                                .await;
-                        } else {
-                            break;
-                        }
-                    }
-                }
+                        },
+                        |attempt, delay, error| {
+                            ui.retrying(
+                                &format!("Pages {range}"),
+                                Some(range),
+                                attempt,
+                                delay,
+                                error,
+                            )
+                        },
+                    )
+                    .await
+                    .map(|pages| {
+                        // Sub-PDFs number their pages from 1
+                        pages
+                            .into_iter()
+                            .map(|page| Page {
+                                number: page.number + range.start - local_range.start,
+                                ..page
+                            })
+                            .collect::<Vec<_>>()
+                    });
 
                 match &result {
-                    Ok(text) => {
-                        let pb_lock = pb.lock().await;
-                        if text.trim().is_empty() {
-                            ui.update_chunk_progress(&pb_lock, "completed", 100);
-                        } else {
-                            ui.update_chunk_progress(&pb_lock, "completed", 100);
-                            ui.print_extraction_stats(chunk_idx + 1, text.len());
+                    Ok(pages) => {
+                        // Checkpoint the range so a rerun can skip it
+                        {
+                            let mut job = job.lock().await;
+                            job.record_range(range, pages);
+                            if let Err(e) = job.save() {
+                                ui.warning(&format!("Failed to save job state: {e}"));
+                            }
                         }
+                        let chars = pages.iter().map(|page| page.text.chars().count()).sum();
+                        ui.range_completed(range, chars);
                     }
-                    Err(e) => {
-                        let pb_lock = pb.lock().await;
-                        ui.update_chunk_progress(&pb_lock, "failed", 100);
-                        ui.print_error(&format!("Failed pages {start_page}-{end_page}: {e}"));
-                    }
+                    Err(e) => ui.range_failed(range, e),
                 }
 
                 (chunk_idx, result)
@@ -145,50 +430,198 @@ pub async fn process_pdf(path: &str, config: &Config) -> Result<String> {
         })
         .collect();
 
-    // Collect results in order
-    let mut results = vec![String::new(); tasks.len()];
-    let mut failed_ranges = Vec::new();
-    let mut last_non_empty_chunk = 0;
+    // Collect results in order, starting from the ranges finished by a previous run
+    let mut results: Vec<Option<Vec<Page>>> = {
+        let job = job.lock().await;
+        ranges
+            .iter()
+            .map(|range| job.completed_pages(*range).map(<[Page]>::to_vec))
+            .collect()
+    };
+    let mut failed = Vec::new();
 
     for task in tasks {
-        let (index, result) = task.await?;
+        let (index, result) = task.await.expect("page range task panicked");
         match result {
-            Ok(text) => {
-                if !text.trim().is_empty() {
-                    results[index] = text;
-                    last_non_empty_chunk = index;
-                }
+            Ok(pages) => results[index] = Some(pages),
+            Err(e) => failed.push(FailedRange::new(ranges[index], &e)),
+        }
+    }
+
+    let mut pages: Vec<Page> = results
+        .into_iter()
+        .zip(&ranges)
+        .flat_map(|(pages, range)| match pages {
+            Some(pages) => pages,
+            None if config.on_failure == FailurePolicy::Placeholder => {
+                range.pages().map(Page::placeholder).collect()
+            }
+            None => Vec::new(),
+        })
+        .collect();
+    pages.extend(local_pages);
+    pages.sort_by_key(|page| page.number);
+
+    // Check Gemini's pages against their text layers, before any local pass
+    let mut verification = Vec::new();
+    if let Some(threshold) = config.verify {
+        let missing = |number: usize| failed.iter().any(|range| range.range().contains(number));
+        verification = pages
+            .iter()
+            .filter(|page| !missing(page.number))
+            .filter_map(|page| {
+                let layer = text_layers.get(&page.number)?;
+                Some(verify::compare(page.number, &page.text, layer, threshold))
+            })
+            .collect();
+        ui.pages_verified(&verification);
+    }
+    // Look for lines in visual order or with mirrored words
+    let mut reversed = Vec::new();
+    if config.bidi != BidiMode::Off {
+        for page in &mut pages {
+            let lines = bidi::reversed_lines(&page.text);
+            if lines.is_empty() {
+                continue;
             }
-            Err(e) => {
-                let start = index * pages_per_chunk + 1;
-                let end = ((index + 1) * pages_per_chunk).min(total_pages);
-                failed_ranges.push((start, end));
-                eprintln!("Pages {start}-{end} failed: {e}");
-                results[index] = format!("[Pages {start}-{end} failed to process]");
+            let repaired = config.bidi == BidiMode::Repair;
+            if repaired {
+                page.map_text(bidi::repair);
             }
+            reversed.push(ReversedLines {
+                page: page.number,
+                lines,
+                repaired,
+            });
+        }
+    }
+    // Count harakat as returned, to spot pages where the model dropped them
+    if config.diacritics != Diacritics::Strip {
+        let counts: Vec<HarakatCount> = pages
+            .iter()
+            .map(|page| HarakatCount::of(page.number, &page.text))
+            .collect();
+        ui.harakat_counted(&counts);
+    }
+    if config.diacritics != Diacritics::Preserve || !config.normalize.is_identity() {
+        for page in &mut pages {
+            page.map_text(|text| config.normalize.apply(&config.diacritics.apply(text)));
         }
     }
+    ui.job_finished(&JobSummary {
+        ranges: num_chunks,
+        succeeded: num_chunks - failed.len(),
+        failed: failed.clone(),
+        total_chars: pages.iter().map(|page| page.text.chars().count()).sum(),
+        reversed,
+    });
 
-    // Trim results to last non-empty chunk
-    results.truncate(last_non_empty_chunk + 1);
+    // Keep the checkpoint around only while there is something left to resume
+    let job = job.lock().await;
+    if failed.is_empty() {
+        job.remove()?;
+        if config.cleanup_uploads {
+            // Uploads in the cache are left for later runs to reuse
+            let cached = |name: &str| {
+                upload_cache
+                    .as_ref()
+                    .is_some_and(|cache| cache.contains_file(name))
+            };
+            let mut deleted = 0;
+            for upload in job.uploads.values().filter(|upload| !cached(&upload.name)) {
+                match client.delete_file(&upload.name).await {
+                    Ok(()) => deleted += 1,
+                    Err(e) => ui.warning(&format!(
+                        "Could not delete uploaded file {}: {e}",
+                        upload.name
+                    )),
+                }
+            }
+            ui.uploads_deleted(deleted);
+        }
+    } else {
+        ui.resume_hint(job.path());
+        if config.on_failure == FailurePolicy::Fail {
+            return Err(Error::RangesFailed { failed });
+        }
+    }
 
-    // Calculate statistics
-    let success_count = results
-        .iter()
-        .filter(|s| !s.is_empty() && !s.starts_with("[Pages"))
-        .count();
-    let total_chars: usize = results.iter().map(|s| s.len()).sum();
+    Ok(Extraction {
+        pages,
+        failed,
+        verification,
+    })
+}
 
-    ui.print_final_summary(num_chunks, success_count, failed_ranges.len(), total_chars);
+/// Content to upload: the input PDF, streamed from disk, or a sub-PDF
+/// built in memory.
+enum UploadSource<'a> {
+    /// The mapped input file together with its SHA-256.
+    File(&'a PdfSource, &'a str),
+    Memory(&'a [u8]),
+}
 
-    // Combine all results
-    let final_text = results
-        .into_iter()
-        .filter(|s| !s.is_empty() && !s.starts_with("[Pages"))
-        .collect::<Vec<_>>()
-        .join("\n\n--- Page Break ---\n\n");
+/// Uploads the content, or reuses the cached upload of identical content
+/// when Gemini still has it and it is `ACTIVE`.
+async fn upload(
+    client: &GeminiClient,
+    config: &Config,
+    ui: &Arc<dyn ProgressSink>,
+    cache: Option<&mut UploadCache>,
+    uploaded_bytes: &mut u64,
+    source: UploadSource<'_>,
+    display_name: &str,
+) -> Result<UploadedFile> {
+    let sha256 = match source {
+        UploadSource::File(_, sha256) => sha256.to_string(),
+        UploadSource::Memory(data) => job::sha256_hex(data),
+    };
+    if let Some(record) = cache.as_ref().and_then(|cache| cache.get(&sha256)) {
+        match client.get_file(&record.name).await {
+            Ok(file) if file.state == gemini_client::FileState::Active => {
+                ui.upload_reused(&file);
+                return Ok(file);
+            }
+            _ => {}
+        }
+    }
 
-    Ok(final_text)
+    // Bytes are reported on top of what earlier uploads of this job sent
+    let base = *uploaded_bytes;
+    let reporter = ui.clone();
+    let progress: UploadProgress = Arc::new(move |sent| reporter.upload_progress(base + sent));
+    let file = config
+        .retry
+        .run(
+            || async {
+                match source {
+                    UploadSource::File(pdf, _) => {
+                        let reader = pdf.reader().await?;
+                        client
+                            .upload_reader(reader, pdf.len() as u64, display_name, progress.clone())
+                            .await
+                    }
+                    UploadSource::Memory(data) => {
+                        client
+                            .upload_with_progress(data, display_name, progress.clone())
+                            .await
+                    }
+                }
+            },
+            |attempt, delay, error| ui.retrying("Upload", None, attempt, delay, error),
+        )
+        .await?;
+    ui.upload_completed(&file);
+    *uploaded_bytes += match source {
+        UploadSource::File(pdf, _) => pdf.len(),
+        UploadSource::Memory(data) => data.len(),
+    } as u64;
+
+    if let Some(cache) = cache {
+        cache.insert(&sha256, &file);
+        cache.save()?;
+    }
+    Ok(file)
 }
 
 #[cfg(test)]
@@ -197,13 +630,358 @@ mod tests {
 
     #[test]
     fn test_chunk_size_calculation() {
-        let data = vec![0u8; 1000];
-        let chunks = chunker::chunk_pdf(&data, 100);
-        assert_eq!(chunks.len(), 10);
+        let pdf = pdf_reader::sample_pdf(12);
+        let document = pdf_reader::load_document(&pdf).unwrap();
+        let ranges = pages::plan_ranges(None, 12, 5).unwrap();
+        let parts = chunker::split_pdf(&document, &ranges).unwrap();
+        assert_eq!(parts.len(), 3);
+    }
+
+    /// Records which upload events a job emitted.
+    #[derive(Default)]
+    struct UploadEvents(std::sync::Mutex<Vec<&'static str>>);
+
+    impl ProgressSink for UploadEvents {
+        fn upload_completed(&self, _file: &UploadedFile) {
+            self.0.lock().unwrap().push("completed");
+        }
+
+        fn upload_reused(&self, _file: &UploadedFile) {
+            self.0.lock().unwrap().push("reused");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_pdf_reuses_cached_upload() {
+        let mut server = mockito::Server::new_async().await;
+        let upload_init = server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/1", server.url()))
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/1")
+            .with_status(200)
+            .with_body(
+                r#"{"file": {"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE",
+                    "expirationTime": "2999-01-01T00:00:00Z"}}"#,
+            )
+            .create_async()
+            .await;
+        let lookup = server
+            .mock("GET", "/v1beta/files/1")
+            .with_status(200)
+            .with_body(r#"{"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .with_status(200)
+            .with_body(
+                r#"{"candidates": [{"content": {"parts": [{"text": "<<<PAGE 1>>>\nنص\n<<<PAGE 2>>>\n"}]}}]}"#,
+            )
+            .create_async()
+            .await;
+        let delete = server
+            .mock("DELETE", "/v1beta/files/1")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let pdf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(pdf.path(), pdf_reader::sample_pdf(2)).unwrap();
+        let events = Arc::new(UploadEvents::default());
+        let config = Config {
+            state_dir: state_dir.path().to_path_buf(),
+            retry: RetryPolicy::none(),
+            rate_limits: RateLimits::unlimited(),
+            endpoint: Some(server.url()),
+            progress: events.clone(),
+            ..Config::new("test_key")
+        };
+
+        // Both runs use the default cleanup and cache settings
+        let path = pdf.path().to_str().unwrap();
+        let expected = [Page::new(1, "نص"), Page::new(2, "")];
+        assert_eq!(process_pdf(path, &config).await.unwrap().pages, expected);
+        assert_eq!(process_pdf(path, &config).await.unwrap().pages, expected);
+
+        upload_init.assert_async().await;
+        lookup.assert_async().await;
+        delete.assert_async().await;
+        assert_eq!(*events.0.lock().unwrap(), ["completed", "reused"]);
+    }
+
+    #[tokio::test]
+    async fn test_successful_run_without_cache_deletes_its_uploads() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/1", server.url()))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/1")
+            .with_status(200)
+            .with_body(
+                r#"{"file": {"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE",
+                    "expirationTime": "2999-01-01T00:00:00Z"}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .with_status(200)
+            .with_body(r#"{"candidates": [{"content": {"parts": [{"text": "نص"}]}}]}"#)
+            .create_async()
+            .await;
+        let delete = server
+            .mock("DELETE", "/v1beta/files/1")
+            .with_status(200)
+            .with_body("{}")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let pdf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(pdf.path(), pdf_reader::sample_pdf(1)).unwrap();
+        let config = Config {
+            state_dir: state_dir.path().to_path_buf(),
+            retry: RetryPolicy::none(),
+            rate_limits: RateLimits::unlimited(),
+            endpoint: Some(server.url()),
+            upload_cache: false,
+            ..Config::new("test_key")
+        };
+
+        process_pdf(pdf.path().to_str().unwrap(), &config)
+            .await
+            .unwrap();
+        delete.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_failure_policies() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/1", server.url()))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/1")
+            .with_status(200)
+            .with_body(
+                r#"{"file": {"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE"}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_body(mockito::Matcher::Regex("pages 2 to 2".to_string()))
+            .with_status(400)
+            .with_body("Invalid argument")
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .match_body(mockito::Matcher::Regex("pages 1 to 1".to_string()))
+            .with_status(200)
+            .with_body(r#"{"candidates": [{"content": {"parts": [{"text": "نص"}]}}]}"#)
+            .create_async()
+            .await;
+
+        let pdf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(pdf.path(), pdf_reader::sample_pdf(2)).unwrap();
+        let path = pdf.path().to_str().unwrap();
+        let run = |on_failure| {
+            let server_url = server.url();
+            async move {
+                let state_dir = tempfile::TempDir::new().unwrap();
+                let config = Config {
+                    pages_per_chunk: 1,
+                    state_dir: state_dir.path().to_path_buf(),
+                    retry: RetryPolicy::none(),
+                    rate_limits: RateLimits::unlimited(),
+                    endpoint: Some(server_url),
+                    on_failure,
+                    ..Config::new("test_key")
+                };
+                process_pdf(path, &config).await
+            }
+        };
+
+        let extraction = run(FailurePolicy::Placeholder).await.unwrap();
+        assert!(!extraction.is_complete());
+        assert_eq!(extraction.failed[0].range(), PageRange::new(2, 2));
+        assert_eq!(extraction.failed[0].status, Some(400));
+        assert_eq!(extraction.pages, [Page::new(1, "نص"), Page::placeholder(2)]);
+
+        let extraction = run(FailurePolicy::Skip).await.unwrap();
+        assert_eq!(extraction.pages, [Page::new(1, "نص")]);
+
+        match run(FailurePolicy::Fail).await {
+            Err(Error::RangesFailed { failed }) => assert_eq!(failed.len(), 1),
+            other => panic!("expected RangesFailed, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_layer_pages_are_not_sent_to_gemini() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/1", server.url()))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/1")
+            .with_status(200)
+            .with_body(
+                r#"{"file": {"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE",
+                    "expirationTime": "2999-01-01T00:00:00Z"}}"#,
+            )
+            .create_async()
+            .await;
+        let generate = server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .with_status(200)
+            .with_body(r#"{"candidates": [{"content": {"parts": [{"text": "نص"}]}}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // Page 1 has an Arabic text layer, page 2 has none
+        let text = "AB CAB CAB CAB CAB CAB CAB C";
+        let pdf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            pdf.path(),
+            pdf_reader::sample_text_layer_pdf(&[&[text], &[]]),
+        )
+        .unwrap();
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            state_dir: state_dir.path().to_path_buf(),
+            retry: RetryPolicy::none(),
+            rate_limits: RateLimits::unlimited(),
+            endpoint: Some(server.url()),
+            ..Config::new("test_key")
+        };
+
+        let extraction = process_pdf(pdf.path().to_str().unwrap(), &config)
+            .await
+            .unwrap();
+        generate.assert_async().await;
+        assert_eq!(extraction.pages.len(), 2);
+        assert!(extraction.pages[0].text.starts_with("بة مبة م"));
+        assert_eq!(extraction.pages[1], Page::new(2, "نص"));
+    }
+
+    #[tokio::test]
+    async fn test_verification_flags_pages_that_differ_from_the_text_layer() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/1", server.url()))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/upload/1")
+            .with_status(200)
+            .with_body(
+                r#"{"file": {"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE",
+                    "expirationTime": "2999-01-01T00:00:00Z"}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .with_status(200)
+            .with_body(
+                r#"{"candidates": [{"content": {"parts": [{"text": "<<<PAGE 1>>>\nنص مختلف تماما\n<<<PAGE 2>>>\nنص"}]}}]}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let text = "AB CAB CAB CAB CAB CAB CAB C";
+        let pdf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            pdf.path(),
+            pdf_reader::sample_text_layer_pdf(&[&[text], &[]]),
+        )
+        .unwrap();
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            state_dir: state_dir.path().to_path_buf(),
+            retry: RetryPolicy::none(),
+            rate_limits: RateLimits::unlimited(),
+            endpoint: Some(server.url()),
+            verify: Some(0.1),
+            ..Config::new("test_key")
+        };
+
+        let extraction = process_pdf(pdf.path().to_str().unwrap(), &config)
+            .await
+            .unwrap();
+        assert_eq!(extraction.pages[0], Page::new(1, "نص مختلف تماما"));
+        // Only page 1 has a text layer to compare with
+        assert_eq!(extraction.verification.len(), 1);
+        assert_eq!(extraction.verification[0].page, 1);
+        assert!(extraction.verification[0].flagged);
     }
 
     #[tokio::test]
-    async fn test_gemini_client_mock() {
-        let _server = mockito::Server::new_async().await;
+    async fn test_range_uploads_without_expiry_are_used() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/upload/v1beta/files")
+            .with_status(200)
+            .with_header("x-goog-upload-url", &format!("{}/upload/1", server.url()))
+            .create_async()
+            .await;
+        // Proxies may leave out expirationTime
+        server
+            .mock("POST", "/upload/1")
+            .with_status(200)
+            .with_body(
+                r#"{"file": {"name": "files/1", "uri": "https://file-uri/1", "state": "ACTIVE"}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1beta/models/gemini-2.5-flash:generateContent")
+            .with_status(200)
+            .with_body(r#"{"candidates": [{"content": {"parts": [{"text": "نص"}]}}]}"#)
+            .create_async()
+            .await;
+
+        // A partial selection is uploaded as a sub-PDF
+        let pdf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(pdf.path(), pdf_reader::sample_pdf(2)).unwrap();
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            pages: Some("2".parse().unwrap()),
+            state_dir: state_dir.path().to_path_buf(),
+            retry: RetryPolicy::none(),
+            rate_limits: RateLimits::unlimited(),
+            endpoint: Some(server.url()),
+            cleanup_uploads: false,
+            ..Config::new("test_key")
+        };
+
+        let extraction = process_pdf(pdf.path().to_str().unwrap(), &config)
+            .await
+            .unwrap();
+        assert_eq!(extraction.pages, [Page::new(2, "نص")]);
     }
 }
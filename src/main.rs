@@ -1,101 +1,476 @@
 use anyhow::Result;
-use arabic_pdf_to_text::{process_pdf, Config};
-use clap::Parser;
+use arabic_pdf_to_text::{
+    bidi::BidiMode,
+    credentials::ApiKeySource,
+    document::{Document, OutputFormat},
+    gemini_client::{GeminiClient, RetryPolicy},
+    markdown,
+    normalize::{Diacritics, Normalization},
+    pages::{self, Page, PageSelection},
+    process_pdf,
+    rate_limit::RateLimits,
+    report::{FailedRange, LogFormat},
+    ui::VerboseUI,
+    verify, Config, Error, Extraction, FailurePolicy,
+};
+use clap::{Parser, Subcommand};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(help = "Path to the Arabic PDF file")]
-    pdf_path: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(required = true, help = "Path to the Arabic PDF file")]
+    pdf_path: Option<String>,
 
     #[arg(
         short,
         long,
+        global = true,
         help = "Gemini API key (can also be set via GEMINI_API_KEY env var)"
     )]
     api_key: Option<String>,
 
+    #[arg(
+        long,
+        global = true,
+        env = "GEMINI_API_KEY_FILE",
+        conflicts_with = "api_key",
+        help = "Read the Gemini API key from a file"
+    )]
+    api_key_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        env = "GEMINI_API_KEY_COMMAND",
+        conflicts_with_all = ["api_key", "api_key_file"],
+        help = "Credential helper command printing the Gemini API key"
+    )]
+    api_key_command: Option<String>,
+
     #[arg(
         short,
         long,
-        default_value = "1048576",
-        help = "Chunk size in bytes (default: 1MB)"
+        default_value = "52428800",
+        help = "Largest PDF in bytes uploaded whole; bigger files are split by page range (default: 50MB)"
     )]
     chunk_size: usize,
 
     #[arg(short, long, help = "Output file path (default: stdout)")]
     output: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "output",
+        help = "Write one file per page (page-0001.txt, .md or .json) into this directory"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "text",
+        help = "Output format: text, markdown, or json for headings, paragraphs, footnotes, tables and captions per page"
+    )]
+    format: OutputFormat,
+
+    #[arg(
+        long,
+        default_value = "report",
+        help = "Lines that read backwards: report them, repair them, or off"
+    )]
+    bidi: BidiMode,
+
+    #[arg(
+        long,
+        default_value = "preserve",
+        help = "Diacritics (tashkeel): preserve, strip, or strip-except-shadda"
+    )]
+    diacritics: Diacritics,
+
+    #[arg(
+        long,
+        default_value = "none",
+        help = "Normalization passes, comma separated: presentation-forms, alef, yaa, taa-marbuta, tatweel, digits=western, digits=arabic, nfc, nfkc, or the presets faithful and search"
+    )]
+    normalize: Normalization,
+
+    #[arg(
+        long,
+        default_value = "\\n\\n--- Page {page} ---\\n\\n",
+        help = "Text between pages; {page} is the number of the next page, \\n a newline"
+    )]
+    page_separator: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Pages to extract, e.g. \"1-10,45,200-\" (default: all pages)"
+    )]
+    pages: Option<PageSelection>,
+
+    #[arg(
+        long,
+        default_value = "5",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Maximum number of pages per extraction request"
+    )]
+    pages_per_chunk: u64,
+
+    #[arg(
+        long,
+        help = "Resume an interrupted job, extracting only the missing page ranges"
+    )]
+    resume: bool,
+
+    #[arg(
+        long,
+        help = "Directory for job checkpoints (default: ~/.cache/arabic_pdf_to_text/jobs)"
+    )]
+    state_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "fail",
+        help = "Page ranges that still fail after all retries: fail the job, or keep a placeholder or skip the missing pages and exit with code 2"
+    )]
+    on_failure: FailurePolicy,
+
+    #[arg(
+        long,
+        help = "Write the failed page ranges as JSON to this file when any are missing"
+    )]
+    failures_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "5",
+        help = "Retries for rate-limited, failed or timed-out requests"
+    )]
+    max_retries: u32,
+
+    #[arg(
+        long,
+        default_value = "2",
+        help = "Initial retry backoff in seconds, doubled on every attempt"
+    )]
+    retry_initial_delay: f64,
+
+    #[arg(long, default_value = "60", help = "Maximum retry backoff in seconds")]
+    retry_max_delay: f64,
+
+    #[arg(
+        long,
+        help = "Maximum requests in flight (default: MAX_CONCURRENT_REQUESTS or 2)"
+    )]
+    max_concurrent_requests: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Request budget per minute, 0 for none (default: REQUESTS_PER_MINUTE or 10)"
+    )]
+    requests_per_minute: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Token budget per minute, 0 for none (default: TOKENS_PER_MINUTE or 250000)"
+    )]
+    tokens_per_minute: Option<u64>,
+
+    #[arg(
+        long,
+        env = "GEMINI_MODEL",
+        default_value = "gemini-2.5-flash",
+        help = "Gemini model used for extraction"
+    )]
+    model: String,
+
+    #[arg(
+        long,
+        global = true,
+        env = "GEMINI_ENDPOINT",
+        help = "API root URL, e.g. a proxy (default: https://generativelanguage.googleapis.com)"
+    )]
+    endpoint: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value = "v1beta",
+        help = "Gemini API version"
+    )]
+    api_version: String,
+
+    #[arg(
+        long,
+        help = "Keep the uploaded files on Gemini after a successful run"
+    )]
+    keep_uploads: bool,
+
+    #[arg(
+        long,
+        help = "Always upload, instead of reusing an earlier upload of identical content"
+    )]
+    no_upload_cache: bool,
+
+    #[arg(
+        long,
+        help = "Send every page to Gemini, even pages with a usable text layer"
+    )]
+    no_text_layer: bool,
+
+    #[arg(
+        long,
+        value_name = "MAX_CER",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Send every page to Gemini and flag pages whose character error rate against the PDF's text layer exceeds MAX_CER"
+    )]
+    verify: Option<Option<f64>>,
+
+    #[arg(
+        long,
+        default_value = "pretty",
+        help = "Progress output on stderr: pretty, plain, jsonl or quiet"
+    )]
+    log_format: LogFormat,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect and delete files uploaded to the Gemini Files API
+    Files {
+        #[command(subcommand)]
+        action: FilesAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FilesAction {
+    /// List every uploaded file
+    List,
+    /// Show the metadata of one file
+    Get {
+        #[arg(help = "File name, e.g. files/abc123")]
+        name: String,
+    },
+    /// Delete files by name
+    Delete {
+        #[arg(required = true, help = "File names, e.g. files/abc123")]
+        names: Vec<String>,
+    },
+    /// Delete every uploaded file in the project
+    Purge {
+        #[arg(
+            long,
+            help = "Delete without asking; otherwise only list what would be deleted"
+        )]
+        yes: bool,
+    },
+}
+
+/// Exit code when output was written but pages are missing.
+const EXIT_INCOMPLETE: i32 = 2;
+
+/// Writes the failed ranges as JSON, e.g. for a script retrying them.
+fn write_failures(path: &std::path::Path, failed: &[FailedRange]) -> Result<()> {
+    let json = serde_json::json!({ "failed_ranges": failed });
+    std::fs::write(path, serde_json::to_string_pretty(&json)?)?;
+    Ok(())
+}
+
+/// Expands `\n` and `\t` in a separator given on the command line.
+fn unescape(text: &str) -> String {
+    text.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+/// Writes each page to its own zero-padded file, e.g. `page-0007.txt`.
+fn write_page_files(dir: &std::path::Path, pages: &[Page], format: OutputFormat) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for page in pages {
+        let path = dir.join(format!("page-{:04}.{}", page.number, extension(format)));
+        match format {
+            OutputFormat::Text | OutputFormat::Markdown => std::fs::write(path, &page.text)?,
+            OutputFormat::Json => std::fs::write(path, serde_json::to_string_pretty(page)?)?,
+        }
+    }
+    Ok(())
+}
+
+fn extension(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Text => "txt",
+        OutputFormat::Json => "json",
+        OutputFormat::Markdown => "md",
+    }
+}
+
+/// Accepts both `abc123` and `files/abc123`.
+fn resource_name(name: &str) -> String {
+    if name.starts_with("files/") {
+        name.to_string()
+    } else {
+        format!("files/{name}")
+    }
+}
+
+async fn run_files_command(client: &GeminiClient, action: FilesAction) -> Result<()> {
+    let ui = VerboseUI::new();
+    match action {
+        FilesAction::List => ui.print_remote_files(&client.list_files().await?),
+        FilesAction::Get { name } => {
+            let file = client.get_file(&resource_name(&name)).await?;
+            ui.print_remote_files(std::slice::from_ref(&file));
+        }
+        FilesAction::Delete { names } => {
+            for name in names {
+                let name = resource_name(&name);
+                client.delete_file(&name).await?;
+                println!("{} Deleted {name}", console::style("🗑️").red());
+            }
+        }
+        FilesAction::Purge { yes } => {
+            let files = client.list_files().await?;
+            ui.print_remote_files(&files);
+            if !yes {
+                if !files.is_empty() {
+                    println!("\nRerun with --yes to delete these files.");
+                }
+                return Ok(());
+            }
+            for file in &files {
+                client.delete_file(&file.name).await?;
+                println!("{} Deleted {}", console::style("🗑️").red(), file.name);
+            }
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let api_key = args
-        .api_key
-        .or_else(|| std::env::var("GEMINI_API_KEY").ok())
-        .ok_or_else(|| {
-            anyhow::anyhow!("API key must be provided via --api-key or GEMINI_API_KEY env var")
+    let key_source = if let Some(key) = args.api_key {
+        ApiKeySource::Literal(key)
+    } else if let Some(path) = args.api_key_file {
+        ApiKeySource::File(path)
+    } else if let Some(command) = args.api_key_command {
+        ApiKeySource::Command(command)
+    } else {
+        let key = std::env::var("GEMINI_API_KEY").map_err(|_| {
+            anyhow::anyhow!(
+                "API key must be provided via --api-key, --api-key-file, --api-key-command or GEMINI_API_KEY env var"
+            )
         })?;
+        ApiKeySource::Literal(key)
+    };
+    let api_key = key_source.resolve()?;
+
+    if let Some(Command::Files { action }) = args.command {
+        let mut builder = GeminiClient::builder(&api_key).api_version(&args.api_version);
+        if let Some(endpoint) = &args.endpoint {
+            builder = builder.base_url(endpoint);
+        }
+        return run_files_command(&builder.build()?, action).await;
+    }
+    let pdf_path = args.pdf_path.expect("clap requires a PDF path");
+
+    // Rate limits come from the environment, overridden by flags
+    let mut rate_limits = RateLimits::from_env()?;
+    if let Some(max) = args.max_concurrent_requests {
+        rate_limits.max_concurrent_requests = max.max(1);
+    }
+    if let Some(rpm) = args.requests_per_minute {
+        rate_limits.requests_per_minute = (rpm > 0).then_some(rpm);
+    }
+    if let Some(tpm) = args.tokens_per_minute {
+        rate_limits.tokens_per_minute = (tpm > 0).then_some(tpm);
+    }
 
-    let config = Config {
-        api_key,
+    let mut config = Config {
         chunk_size: args.chunk_size,
+        pages: args.pages,
+        pages_per_chunk: args.pages_per_chunk as usize,
+        resume: args.resume,
+        retry: RetryPolicy {
+            max_retries: args.max_retries,
+            initial_backoff: Duration::try_from_secs_f64(args.retry_initial_delay)?,
+            max_backoff: Duration::try_from_secs_f64(args.retry_max_delay)?,
+        },
+        rate_limits,
+        model: args.model,
+        api_version: args.api_version,
+        endpoint: args.endpoint,
+        cleanup_uploads: !args.keep_uploads,
+        upload_cache: !args.no_upload_cache,
+        text_layer: !args.no_text_layer,
+        verify: args
+            .verify
+            .map(|threshold| threshold.unwrap_or(verify::DEFAULT_THRESHOLD)),
+        on_failure: args.on_failure,
+        format: args.format,
+        bidi: args.bidi,
+        diacritics: args.diacritics,
+        normalize: args.normalize,
+        progress: args.log_format.sink(),
+        ..Config::new(&api_key)
     };
+    if let Some(state_dir) = args.state_dir {
+        config.state_dir = state_dir;
+    }
 
     let start_time = std::time::Instant::now();
 
-    let result = process_pdf(&args.pdf_path, &config).await?;
+    let extraction = match process_pdf(&pdf_path, &config).await {
+        Err(Error::RangesFailed { failed }) => {
+            if let Some(path) = &args.failures_file {
+                write_failures(path, &failed)?;
+            }
+            return Err(Error::RangesFailed { failed }.into());
+        }
+        result => result?,
+    };
+    let Extraction { pages, failed, .. } = extraction;
 
     let elapsed = start_time.elapsed();
 
-    if let Some(output_path) = args.output {
-        println!(
-            "\n{} {}",
-            console::style("💾").cyan(),
-            console::style("Saving extracted text to file...")
-                .cyan()
-                .bold()
-        );
-
-        std::fs::write(&output_path, &result)?;
-
-        println!(
-            "{} {}",
-            console::style("✅").green(),
-            console::style(format!("Output saved to: {output_path}"))
-                .green()
-                .bold()
-        );
-
-        println!(
-            "\n{} {}",
-            console::style("⏱️").cyan(),
-            console::style(format!(
-                "Total processing time: {:.2} seconds",
-                elapsed.as_secs_f64()
-            ))
-            .cyan()
-        );
+    // Only the extracted text goes to stdout, so it can be piped
+    let output_dir = args
+        .output_dir
+        .as_ref()
+        .map(|dir| dir.display().to_string());
+    if let Some(dir) = &args.output_dir {
+        write_page_files(dir, &pages, config.format)?;
     } else {
-        println!("\n{}", console::style("═".repeat(65)).cyan().bright());
-        println!(
-            "{} {}",
-            console::style("📜").cyan(),
-            console::style("EXTRACTED TEXT").cyan().bold().underlined()
-        );
-        println!("{}", console::style("═".repeat(65)).cyan().bright());
-        println!("\n{result}");
-        println!("\n{}", console::style("═".repeat(65)).cyan().bright());
+        let text = match config.format {
+            OutputFormat::Text => pages::join_pages(&pages, &unescape(&args.page_separator)),
+            OutputFormat::Json => serde_json::to_string_pretty(&Document {
+                pages,
+                failed: failed.clone(),
+            })?,
+            OutputFormat::Markdown => markdown::join_pages(&pages, &unescape(&args.page_separator)),
+        };
+        if let Some(output_path) = &args.output {
+            std::fs::write(output_path, &text)?;
+        } else {
+            println!("{text}");
+        }
     }
+    config
+        .progress
+        .output_written(args.output.as_deref().or(output_dir.as_deref()), elapsed);
 
-    println!(
-        "\n{} {}",
-        console::style("🎯").green(),
-        console::style("Mission accomplished! Have a great day! 🌟")
-            .green()
-            .bold()
-    );
-
+    if !failed.is_empty() {
+        if let Some(path) = &args.failures_file {
+            write_failures(path, &failed)?;
+        }
+        std::io::stdout().flush()?;
+        std::process::exit(EXIT_INCOMPLETE);
+    }
     Ok(())
 }
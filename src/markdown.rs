@@ -0,0 +1,267 @@
+use crate::pages::Page;
+
+/// Right-to-left mark, keeping table cells that start with digits or Latin
+/// text in right-to-left order.
+const RLM: char = '\u{200F}';
+
+/// Checks the Markdown of one page and tidies it up.
+///
+/// Code fences must be closed and every table needs a delimiter row
+/// matching its header, except for rows at the top of the page that
+/// continue a table from the previous page. Short table rows are padded,
+/// cells are made safe for right-to-left rendering and `##Title` headings
+/// get their missing space.
+pub fn normalize_page(text: &str) -> Result<String, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut output = Vec::with_capacity(lines.len());
+    let mut in_fence = false;
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        } else if !in_fence && is_table_row(line) {
+            let start = index;
+            while index < lines.len() && is_table_row(lines[index]) {
+                index += 1;
+            }
+            let at_top = lines[..start].iter().all(|line| line.trim().is_empty());
+            output.extend(normalize_table(&lines[start..index], at_top)?);
+            continue;
+        } else if !in_fence {
+            output.push(fix_heading(line));
+            index += 1;
+            continue;
+        }
+        output.push(line.to_string());
+        index += 1;
+    }
+
+    if in_fence {
+        return Err("a code block is never closed".to_string());
+    }
+    Ok(output.join("\n"))
+}
+
+/// Joins the Markdown of consecutive pages.
+///
+/// Pages are separated by `separator` (with `{page}` replaced by the number
+/// of the next page) unless a table or list runs across the boundary; those
+/// are joined directly, dropping a table header that the next page repeats.
+pub fn join_pages(pages: &[Page], separator: &str) -> String {
+    let mut text = String::new();
+    let mut previous: Option<&Page> = None;
+    for page in pages {
+        let mut body = page.text.as_str();
+        if let Some(previous) = previous {
+            match continuation(&previous.text, body) {
+                Some(rest) => {
+                    body = rest;
+                    text.push('\n');
+                }
+                None => text.push_str(&separator.replace("{page}", &page.number.to_string())),
+            }
+        }
+        text.push_str(body);
+        previous = Some(page);
+    }
+    text
+}
+
+/// If `next` continues a table or list that `previous` ends with, returns
+/// `next` without a repeated table header.
+fn continuation<'a>(previous: &str, next: &'a str) -> Option<&'a str> {
+    let last = previous
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())?;
+    let next = next.trim_start_matches(['\n', '\r']);
+    let first = next.lines().next()?;
+
+    if is_table_row(last) && is_table_row(first) {
+        let mut lines = next.lines();
+        let header = lines.next()?;
+        let repeats_header = lines.next().is_some_and(is_delimiter_row)
+            && split_row(header) == split_row(table_header(previous)?);
+        if !repeats_header {
+            return Some(next);
+        }
+        // Skip the repeated header and its delimiter row
+        let skip: usize = next.lines().take(2).map(|line| line.len() + 1).sum();
+        return Some(next.get(skip..).unwrap_or(""));
+    }
+
+    match (list_marker(last), list_marker(first)) {
+        (Some(ordered), Some(next_ordered)) if ordered == next_ordered => Some(next),
+        _ => None,
+    }
+}
+
+/// The header row of the table that `text` ends with.
+fn table_header(text: &str) -> Option<&str> {
+    let rows: Vec<&str> = text
+        .lines()
+        .rev()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| is_table_row(line))
+        .collect();
+    rows.windows(2)
+        .find(|pair| is_delimiter_row(pair[0]))
+        .map(|pair| pair[1])
+}
+
+fn is_table_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+fn is_delimiter_row(line: &str) -> bool {
+    let cells = split_row(line);
+    !cells.is_empty()
+        && cells.iter().all(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            !dashes.is_empty() && dashes.chars().all(|c| c == '-')
+        })
+}
+
+/// Splits a table row at unescaped pipes.
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line,
+    };
+
+    let mut cells = vec![String::new()];
+    let mut escaped = false;
+    for c in line.chars() {
+        if c == '|' && !escaped {
+            cells.push(String::new());
+        } else {
+            cells.last_mut().unwrap().push(c);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+fn normalize_table(rows: &[&str], at_top: bool) -> Result<Vec<String>, String> {
+    let has_header = rows.len() >= 2 && is_delimiter_row(rows[1]);
+    if !has_header && !at_top {
+        return Err(format!(
+            "the table starting with '{}' has no delimiter row",
+            rows[0].trim()
+        ));
+    }
+
+    let cells: Vec<Vec<String>> = rows.iter().map(|row| split_row(row)).collect();
+    let columns = if has_header {
+        if cells[1].len() != cells[0].len() {
+            return Err(format!(
+                "a table header has {} columns but its delimiter row {}",
+                cells[0].len(),
+                cells[1].len()
+            ));
+        }
+        cells[0].len()
+    } else {
+        cells.iter().map(Vec::len).max().unwrap_or(0)
+    };
+
+    let arabic = rows.iter().any(|row| row.chars().any(is_rtl));
+    cells
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut row)| {
+            if row.len() > columns {
+                return Err(format!(
+                    "a table row has {} cells but the table {columns} columns",
+                    row.len()
+                ));
+            }
+            row.resize(columns, String::new());
+            if !(has_header && index == 1) && arabic {
+                row = row.into_iter().map(rtl_safe).collect();
+            }
+            Ok(format!("| {} |", row.join(" | ")))
+        })
+        .collect()
+}
+
+/// Prefixes a cell starting with a digit, Latin letter or punctuation with
+/// a right-to-left mark, so that renderers keep it in right-to-left order.
+fn rtl_safe(cell: String) -> String {
+    match cell.chars().next() {
+        Some(first) if !is_rtl(first) && first != RLM => format!("{RLM}{cell}"),
+        _ => cell,
+    }
+}
+
+fn is_rtl(c: char) -> bool {
+    matches!(c, '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+/// `##Title` becomes `## Title`.
+fn fix_heading(line: &str) -> String {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    match line[hashes..].chars().next() {
+        Some(c) if (1..=6).contains(&hashes) && !c.is_whitespace() => {
+            format!("{} {}", &line[..hashes], &line[hashes..])
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// `Some(true)` for an ordered list item, `Some(false)` for a bullet.
+fn list_marker(line: &str) -> Option<bool> {
+    let line = line.trim_start();
+    if line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ") {
+        return Some(false);
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    (digits > 0 && (rest.starts_with(". ") || rest.starts_with(") "))).then_some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_page_tidies_tables_and_headings() {
+        let text = "##العنوان\n\n| الاسم | العدد |\n|---|---|\n| زيد | 30 |\n| عمرو |";
+        assert_eq!(
+            normalize_page(text).unwrap(),
+            "## العنوان\n\n| الاسم | العدد |\n| --- | --- |\n| زيد | \u{200F}30 |\n| عمرو |  |"
+        );
+    }
+
+    #[test]
+    fn test_normalize_page_rejects_broken_structure() {
+        assert!(normalize_page("نص\n\n| أ | ب |\n| ج | د |").is_err());
+        assert!(normalize_page("| أ | ب |\n| --- |").is_err());
+        assert!(normalize_page("```\nكود").is_err());
+        // Rows at the top of a page may continue a table
+        assert!(normalize_page("| ج | د |\n\nنص").is_ok());
+    }
+
+    #[test]
+    fn test_join_pages_continues_tables_and_lists() {
+        let pages = [
+            Page::new(1, "| أ | ب |\n| --- | --- |\n| ١ | ٢ |"),
+            Page::new(2, "| أ | ب |\n| --- | --- |\n| ٣ | ٤ |\n\n1. أول"),
+            Page::new(3, "2. ثان"),
+            Page::new(4, "فقرة"),
+        ];
+        assert_eq!(
+            join_pages(&pages, "\n\n<!-- {page} -->\n\n"),
+            "| أ | ب |\n| --- | --- |\n| ١ | ٢ |\n| ٣ | ٤ |\n\n1. أول\n2. ثان\n\n<!-- 4 -->\n\nفقرة"
+        );
+    }
+
+    #[test]
+    fn test_split_row_keeps_escaped_pipes() {
+        assert_eq!(split_row(r"| a \| b | c |"), [r"a \| b", "c"]);
+    }
+}
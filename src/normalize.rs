@@ -0,0 +1,356 @@
+use crate::error::{Error, Result};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+const TATWEEL: char = '\u{0640}';
+const SHADDA: char = '\u{0651}';
+
+/// What happens to diacritics (tashkeel), both in the extraction prompt
+/// and in a local pass over the answer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Diacritics {
+    /// Keep every diacritic exactly as printed.
+    #[default]
+    Preserve,
+    /// Remove all diacritics.
+    Strip,
+    /// Remove all diacritics but shadda.
+    StripExceptShadda,
+}
+
+impl Diacritics {
+    /// The instruction added to extraction prompts.
+    pub fn instruction(self) -> &'static str {
+        match self {
+            Self::Preserve => {
+                "Keep every diacritic (harakat, tanween, shadda, sukun, dagger alef) exactly \
+                 as printed, without adding missing ones or dropping any."
+            }
+            Self::Strip => "Leave out all diacritics (harakat, tanween, shadda, sukun).",
+            Self::StripExceptShadda => {
+                "Leave out all diacritics (harakat, tanween, sukun) except shadda, \
+                 which must be kept wherever it is printed."
+            }
+        }
+    }
+
+    /// Removes the diacritics the mode does not keep, in case the model
+    /// kept some anyway.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Self::Preserve => text.to_string(),
+            Self::Strip => text.chars().filter(|&c| !is_diacritic(c)).collect(),
+            Self::StripExceptShadda => text
+                .chars()
+                .filter(|&c| c == SHADDA || !is_diacritic(c))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Diacritics {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "preserve" => Ok(Self::Preserve),
+            "strip" => Ok(Self::Strip),
+            "strip-except-shadda" => Ok(Self::StripExceptShadda),
+            other => Err(Error::InvalidInput(format!(
+                "unknown diacritics mode '{other}' (expected preserve, strip or strip-except-shadda)"
+            ))),
+        }
+    }
+}
+
+/// Harakat, tanween, shadda, sukun, the dagger alef and Quranic
+/// annotation signs.
+pub(crate) fn is_diacritic(c: char) -> bool {
+    matches!(
+        c,
+        '\u{064B}'..='\u{065F}'
+            | '\u{0670}'
+            | '\u{06D6}'..='\u{06DC}'
+            | '\u{06DF}'..='\u{06E4}'
+            | '\u{06E7}'..='\u{06E8}'
+            | '\u{06EA}'..='\u{06ED}'
+    )
+}
+
+/// The harakat counted by [`HarakatCount`]: fathatan to sukun and the
+/// dagger alef.
+fn is_haraka(c: char) -> bool {
+    matches!(c, '\u{064B}'..='\u{0652}' | '\u{0670}')
+}
+
+fn is_arabic_letter(c: char) -> bool {
+    matches!(c, '\u{0621}'..='\u{063A}' | '\u{0641}'..='\u{064A}' | '\u{0671}'..='\u{06D3}')
+}
+
+/// Pages with at least this many letters are expected to carry harakat
+/// when the rest of the document does.
+const MIN_LETTERS_FOR_HARAKAT: usize = 50;
+
+/// How many harakat the model returned for a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct HarakatCount {
+    pub page: usize,
+    pub harakat: usize,
+    pub letters: usize,
+}
+
+impl HarakatCount {
+    pub fn of(page: usize, text: &str) -> Self {
+        let mut count = Self {
+            page,
+            harakat: 0,
+            letters: 0,
+        };
+        for c in text.chars() {
+            if is_haraka(c) {
+                count.harakat += 1;
+            } else if is_arabic_letter(c) {
+                count.letters += 1;
+            }
+        }
+        count
+    }
+
+    /// Harakat per letter.
+    pub fn density(&self) -> f64 {
+        self.harakat as f64 / self.letters.max(1) as f64
+    }
+}
+
+/// Pages of a vocalised document that came back without any harakat,
+/// suggesting the model dropped them.
+pub fn pages_missing_harakat(counts: &[HarakatCount]) -> Vec<usize> {
+    if counts.iter().all(|count| count.harakat == 0) {
+        return Vec::new();
+    }
+    counts
+        .iter()
+        .filter(|count| count.harakat == 0 && count.letters >= MIN_LETTERS_FOR_HARAKAT)
+        .map(|count| count.page)
+        .collect()
+}
+
+/// Which digits numbers are written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Digits {
+    /// `0123456789`
+    Western,
+    /// `٠١٢٣٤٥٦٧٨٩`
+    ArabicIndic,
+}
+
+/// Unicode normalization form applied to the whole text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    Nfc,
+    /// Also folds compatibility characters such as ligatures and
+    /// presentation forms.
+    Nfkc,
+}
+
+/// Post-processing passes applied to extracted text, all off by default.
+///
+/// Faithful transcription wants little more than [`Self::faithful`], while
+/// search indexing folds spelling variants with [`Self::search`]. Parsed
+/// from a comma-separated list of pass names, see [`FromStr`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Normalization {
+    /// Arabic Presentation Forms (U+FB50–U+FDFF, U+FE70–U+FEFF) become base
+    /// letters.
+    pub presentation_forms: bool,
+    /// `أ إ آ ٱ` become `ا`, `ؤ` becomes `و` and `ئ` becomes `ي`.
+    pub alef_hamza: bool,
+    /// Alef maqsura `ى` becomes `ي`.
+    pub yaa: bool,
+    /// Taa marbuta `ة` becomes `ه`.
+    pub taa_marbuta: bool,
+    /// Removes the tatweel (kashida) `ـ`.
+    pub tatweel: bool,
+    pub digits: Option<Digits>,
+    pub unicode: Option<UnicodeForm>,
+}
+
+impl Normalization {
+    /// Keeps the text as written, only repairing presentation forms and
+    /// composing characters.
+    pub fn faithful() -> Self {
+        Self {
+            presentation_forms: true,
+            unicode: Some(UnicodeForm::Nfc),
+            ..Self::default()
+        }
+    }
+
+    /// Folds every variant that search users do not tell apart.
+    pub fn search() -> Self {
+        Self {
+            presentation_forms: true,
+            alef_hamza: true,
+            yaa: true,
+            taa_marbuta: true,
+            tatweel: true,
+            digits: Some(Digits::Western),
+            unicode: Some(UnicodeForm::Nfkc),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        if self.is_identity() {
+            return text.to_string();
+        }
+
+        let mut text = if self.presentation_forms {
+            let mut folded = String::with_capacity(text.len());
+            for c in text.chars() {
+                if is_presentation_form(c) {
+                    folded.extend(c.to_string().nfkc());
+                } else {
+                    folded.push(c);
+                }
+            }
+            folded
+        } else {
+            text.to_string()
+        };
+        text = match self.unicode {
+            Some(UnicodeForm::Nfc) => text.nfc().collect(),
+            Some(UnicodeForm::Nfkc) => text.nfkc().collect(),
+            None => text,
+        };
+
+        text.chars()
+            .filter(|&c| !(self.tatweel && c == TATWEEL))
+            .map(|c| match c {
+                'أ' | 'إ' | 'آ' | 'ٱ' | 'ٲ' | 'ٳ' if self.alef_hamza => 'ا',
+                'ؤ' if self.alef_hamza => 'و',
+                'ئ' if self.alef_hamza => 'ي',
+                'ى' if self.yaa => 'ي',
+                'ة' if self.taa_marbuta => 'ه',
+                _ => match self.digits {
+                    Some(digits) => convert_digit(c, digits),
+                    None => c,
+                },
+            })
+            .collect()
+    }
+}
+
+fn is_presentation_form(c: char) -> bool {
+    matches!(c, '\u{FB50}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+fn convert_digit(c: char, digits: Digits) -> char {
+    let value = match c {
+        '0'..='9' => c as u32 - '0' as u32,
+        '\u{0660}'..='\u{0669}' => c as u32 - 0x0660,
+        // Extended (Persian) digits
+        '\u{06F0}'..='\u{06F9}' => c as u32 - 0x06F0,
+        _ => return c,
+    };
+    let zero = match digits {
+        Digits::Western => '0' as u32,
+        Digits::ArabicIndic => 0x0660,
+    };
+    char::from_u32(zero + value).unwrap_or(c)
+}
+
+impl FromStr for Normalization {
+    type Err = Error;
+
+    /// Parses passes such as `presentation-forms,alef,tatweel,nfc`, or the
+    /// presets `none`, `faithful` and `search`; later items add to earlier
+    /// ones.
+    fn from_str(value: &str) -> Result<Self> {
+        let mut normalization = Self::default();
+        for pass in value.split(',').map(str::trim) {
+            match pass {
+                "none" => normalization = Self::default(),
+                "faithful" => normalization = Self::faithful(),
+                "search" => normalization = Self::search(),
+                "presentation-forms" => normalization.presentation_forms = true,
+                "alef" | "alef-hamza" => normalization.alef_hamza = true,
+                "yaa" => normalization.yaa = true,
+                "taa-marbuta" => normalization.taa_marbuta = true,
+                "tatweel" => normalization.tatweel = true,
+                "digits=western" => normalization.digits = Some(Digits::Western),
+                "digits=arabic" => normalization.digits = Some(Digits::ArabicIndic),
+                "nfc" => normalization.unicode = Some(UnicodeForm::Nfc),
+                "nfkc" => normalization.unicode = Some(UnicodeForm::Nfkc),
+                other => {
+                    return Err(Error::InvalidInput(format!(
+                        "unknown normalization pass '{other}' (expected none, faithful, search, \
+                         presentation-forms, alef, yaa, taa-marbuta, tatweel, digits=western, \
+                         digits=arabic, nfc or nfkc)"
+                    )))
+                }
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presentation_forms_become_base_letters() {
+        // "سلام" in isolated and final presentation forms, and the lam-alef ligature
+        let text = "\u{FEB3}\u{FEFC}\u{FEE1}";
+        let normalization: Normalization = "presentation-forms".parse().unwrap();
+        assert_eq!(normalization.apply(text), "سلام");
+    }
+
+    #[test]
+    fn test_search_preset_folds_variants() {
+        let text = "إِسلامـيّة أحمد مُوسى ٢٠٢٤ ۱۹";
+        assert_eq!(
+            Normalization::search().apply(text),
+            "اِسلاميّه احمد مُوسي 2024 19"
+        );
+    }
+
+    #[test]
+    fn test_diacritics_modes() {
+        let text = "إِنَّ اللَّهَ";
+        assert_eq!(Diacritics::Preserve.apply(text), text);
+        assert_eq!(Diacritics::Strip.apply(text), "إن الله");
+        assert_eq!(Diacritics::StripExceptShadda.apply(text), "إنّ اللّه");
+        assert_eq!(
+            "strip-except-shadda".parse::<Diacritics>().unwrap(),
+            Diacritics::StripExceptShadda
+        );
+    }
+
+    #[test]
+    fn test_pages_missing_harakat() {
+        let vocalised = "بِسْمِ اللَّهِ الرَّحْمَنِ الرَّحِيمِ ".repeat(5);
+        let bare = "بسم الله الرحمن الرحيم ".repeat(5);
+        let counts = [
+            HarakatCount::of(1, &vocalised),
+            HarakatCount::of(2, &bare),
+            HarakatCount::of(3, "قصير"),
+        ];
+        assert_eq!(counts[0].letters, counts[1].letters);
+        assert!(counts[0].density() > 0.5);
+        assert_eq!(pages_missing_harakat(&counts), [2]);
+        assert!(pages_missing_harakat(&counts[1..]).is_empty());
+    }
+
+    #[test]
+    fn test_passes_are_selectable() {
+        let normalization: Normalization = "tatweel,digits=arabic".parse().unwrap();
+        assert_eq!(normalization.apply("كتـاب 12 ة"), "كتاب ١٢ ة");
+        assert!(Normalization::default().is_identity());
+        assert!("nfc,bogus".parse::<Normalization>().is_err());
+    }
+}
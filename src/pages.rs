@@ -0,0 +1,446 @@
+use crate::document::Block;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// An inclusive, 1-based range of PDF pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PageRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end + 1 - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    pub fn contains(&self, page: usize) -> bool {
+        (self.start..=self.end).contains(&page)
+    }
+
+    pub fn pages(&self) -> impl Iterator<Item = usize> {
+        self.start..=self.end
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// The extracted text of one PDF page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page {
+    /// 1-based position of the page in the PDF, not the number printed on it.
+    pub number: usize,
+    pub text: String,
+    /// The page's structure in reading order; only filled by structured
+    /// extraction, see [`OutputFormat::Json`](crate::document::OutputFormat).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<Block>,
+}
+
+impl Page {
+    pub fn new(number: usize, text: impl Into<String>) -> Self {
+        Self {
+            number,
+            text: text.into(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Stands in for a page whose extraction failed.
+    pub fn placeholder(number: usize) -> Self {
+        Self::new(number, format!("[Page {number} failed to process]"))
+    }
+
+    /// Applies `f` to the text of the page and of its blocks.
+    pub fn map_text(&mut self, f: impl Fn(&str) -> String) {
+        self.text = f(&self.text);
+        for block in &mut self.blocks {
+            block.map_text(&f);
+        }
+    }
+
+    /// A page whose text is the plain text of its blocks.
+    pub fn structured(number: usize, blocks: Vec<Block>) -> Self {
+        let text = blocks
+            .iter()
+            .map(Block::plain_text)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Self {
+            number,
+            text,
+            blocks,
+        }
+    }
+}
+
+/// The line Gemini is asked to put before the text of each page.
+pub fn page_marker(number: usize) -> String {
+    format!("<<<PAGE {number}>>>")
+}
+
+fn parse_page_marker(line: &str) -> Option<usize> {
+    line.trim()
+        .strip_prefix("<<<PAGE ")?
+        .strip_suffix(">>>")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Splits a response for `range` at its [`page_marker`] lines, see
+/// [`complete_pages`]. A response without any marker is accepted for a
+/// single page.
+pub fn split_pages(text: &str, range: PageRange) -> Result<Vec<Page>> {
+    let mut pages: Vec<Page> = Vec::new();
+    let mut preamble = String::new();
+
+    for line in text.lines() {
+        if let Some(number) = parse_page_marker(line) {
+            pages.push(Page::new(number, ""));
+            continue;
+        }
+        let current = match pages.last_mut() {
+            Some(page) => &mut page.text,
+            None => &mut preamble,
+        };
+        current.push_str(line);
+        current.push('\n');
+    }
+
+    let invalid = |message: &str| Error::PageMarkers {
+        range,
+        message: message.to_string(),
+    };
+    if pages.is_empty() {
+        if range.len() > 1 && !preamble.trim().is_empty() {
+            return Err(invalid("the response has no page markers"));
+        }
+        pages.push(Page::new(range.start, preamble));
+    } else if !preamble.trim().is_empty() {
+        return Err(invalid("text precedes the first page marker"));
+    }
+
+    for page in &mut pages {
+        page.text = page.text.trim().to_string();
+    }
+    complete_pages(pages, range)
+}
+
+/// Checks the pages Gemini returned for `range` and fills in the rest.
+///
+/// Page numbers must lie within the range and increase strictly. Pages
+/// that were not returned come back empty, as Gemini skips blank pages.
+pub fn complete_pages(found: Vec<Page>, range: PageRange) -> Result<Vec<Page>> {
+    let invalid = |message: String| Error::PageMarkers { range, message };
+    let mut last = None;
+    for page in &found {
+        if !range.contains(page.number) {
+            return Err(invalid(format!("page {} was not requested", page.number)));
+        }
+        if let Some(last) = last.filter(|&last| page.number <= last) {
+            return Err(invalid(format!("page {} follows page {last}", page.number)));
+        }
+        last = Some(page.number);
+    }
+
+    let mut found = found.into_iter().peekable();
+    Ok(range
+        .pages()
+        .map(|number| {
+            found
+                .next_if(|page| page.number == number)
+                .unwrap_or_else(|| Page::new(number, ""))
+        })
+        .collect())
+}
+
+/// Joins pages into one text, putting `separator` between consecutive
+/// pages. `{page}` in the separator is replaced by the number of the page
+/// that follows it.
+pub fn join_pages(pages: &[Page], separator: &str) -> String {
+    let mut text = String::new();
+    for (index, page) in pages.iter().enumerate() {
+        if index > 0 {
+            text.push_str(&separator.replace("{page}", &page.number.to_string()));
+        }
+        text.push_str(&page.text);
+    }
+    text
+}
+
+/// A user-supplied page selection such as `1-10,45,200-210` or `300-`.
+///
+/// Items are comma separated; each is a single page, a closed range or a
+/// range that is open towards the end of the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageSelection {
+    items: Vec<(usize, Option<usize>)>,
+}
+
+impl FromStr for PageSelection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut items = Vec::new();
+
+        for item in s.split(',').map(str::trim) {
+            if item.is_empty() {
+                return Err(Error::InvalidInput(format!(
+                    "Empty item in page selection '{s}'"
+                )));
+            }
+
+            let (start, end) = match item.split_once('-') {
+                Some((start, "")) => (parse_page(start)?, None),
+                Some((start, end)) => (parse_page(start)?, Some(parse_page(end)?)),
+                None => {
+                    let page = parse_page(item)?;
+                    (page, Some(page))
+                }
+            };
+
+            if let Some(end) = end {
+                if end < start {
+                    return Err(Error::InvalidInput(format!(
+                        "Page range '{item}' ends before it starts"
+                    )));
+                }
+            }
+            items.push((start, end));
+        }
+
+        Ok(Self { items })
+    }
+}
+
+fn parse_page(s: &str) -> Result<usize> {
+    let page: usize = s
+        .trim()
+        .parse()
+        .map_err(|_| Error::InvalidInput(format!("Invalid page number '{s}'")))?;
+    if page == 0 {
+        return Err(Error::InvalidInput("Page numbers start at 1".to_string()));
+    }
+    Ok(page)
+}
+
+impl PageSelection {
+    /// Resolves the selection against a document, returning sorted,
+    /// non-overlapping ranges with adjacent items merged.
+    pub fn resolve(&self, total_pages: usize) -> Result<Vec<PageRange>> {
+        let mut ranges = Vec::with_capacity(self.items.len());
+        for &(start, end) in &self.items {
+            let end = end.unwrap_or(total_pages);
+            if start > total_pages || end > total_pages {
+                return Err(Error::PageOutOfRange {
+                    page: end.max(start),
+                    total_pages,
+                });
+            }
+            ranges.push(PageRange::new(start, end));
+        }
+
+        ranges.sort();
+        let mut merged: Vec<PageRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end + 1 => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        Ok(merged)
+    }
+}
+
+/// Plans the page ranges sent to Gemini, one request per returned range.
+///
+/// Without a selection the whole document is planned. Ranges never span a
+/// gap in the selection, so the last chunk of each selected run may be
+/// shorter than `pages_per_chunk`.
+pub fn plan_ranges(
+    selection: Option<&PageSelection>,
+    total_pages: usize,
+    pages_per_chunk: usize,
+) -> Result<Vec<PageRange>> {
+    if pages_per_chunk == 0 {
+        return Err(Error::InvalidInput(
+            "Pages per chunk must be at least 1".to_string(),
+        ));
+    }
+
+    let selected = match selection {
+        Some(selection) => selection.resolve(total_pages)?,
+        None if total_pages == 0 => Vec::new(),
+        None => vec![PageRange::new(1, total_pages)],
+    };
+
+    Ok(chunk_runs(selected, pages_per_chunk))
+}
+
+/// Plans the page ranges for a sorted list of pages, like [`plan_ranges`]
+/// does for a selection.
+pub fn plan_pages(pages: &[usize], pages_per_chunk: usize) -> Result<Vec<PageRange>> {
+    if pages_per_chunk == 0 {
+        return Err(Error::InvalidInput(
+            "Pages per chunk must be at least 1".to_string(),
+        ));
+    }
+
+    let mut runs: Vec<PageRange> = Vec::new();
+    for &page in pages {
+        match runs.last_mut() {
+            Some(last) if page == last.end + 1 => last.end = page,
+            _ => runs.push(PageRange::new(page, page)),
+        }
+    }
+    Ok(chunk_runs(runs, pages_per_chunk))
+}
+
+fn chunk_runs(runs: Vec<PageRange>, pages_per_chunk: usize) -> Vec<PageRange> {
+    runs.into_iter()
+        .flat_map(|run| {
+            (run.start..=run.end)
+                .step_by(pages_per_chunk)
+                .map(move |start| PageRange::new(start, (start + pages_per_chunk - 1).min(run.end)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(pairs: &[(usize, usize)]) -> Vec<PageRange> {
+        pairs.iter().map(|&(s, e)| PageRange::new(s, e)).collect()
+    }
+
+    #[test]
+    fn test_plan_whole_document() {
+        let plan = plan_ranges(None, 12, 5).unwrap();
+        assert_eq!(plan, ranges(&[(1, 5), (6, 10), (11, 12)]));
+    }
+
+    #[test]
+    fn test_plan_single_page_chunks() {
+        let plan = plan_ranges(None, 3, 1).unwrap();
+        assert_eq!(plan, ranges(&[(1, 1), (2, 2), (3, 3)]));
+    }
+
+    #[test]
+    fn test_plan_selection_with_odd_chunk_size() {
+        let selection: PageSelection = "1-10,45,200-210".parse().unwrap();
+        let plan = plan_ranges(Some(&selection), 600, 3).unwrap();
+        assert_eq!(
+            plan,
+            ranges(&[
+                (1, 3),
+                (4, 6),
+                (7, 9),
+                (10, 10),
+                (45, 45),
+                (200, 202),
+                (203, 205),
+                (206, 208),
+                (209, 210),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_plan_pages_splits_at_gaps() {
+        assert_eq!(
+            plan_pages(&[2, 3, 4, 5, 9, 11, 12], 3).unwrap(),
+            ranges(&[(2, 4), (5, 5), (9, 9), (11, 12)])
+        );
+        assert!(plan_pages(&[], 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_selection_merges_overlapping_items() {
+        let selection: PageSelection = "8-12, 1-3,4,10-".parse().unwrap();
+        assert_eq!(selection.resolve(15).unwrap(), ranges(&[(1, 4), (8, 15)]));
+    }
+
+    #[test]
+    fn test_selection_out_of_range() {
+        let selection: PageSelection = "5-40".parse().unwrap();
+        assert!(matches!(
+            plan_ranges(Some(&selection), 30, 5),
+            Err(Error::PageOutOfRange {
+                page: 40,
+                total_pages: 30
+            })
+        ));
+    }
+
+    #[test]
+    fn test_selection_parse_errors() {
+        assert!("".parse::<PageSelection>().is_err());
+        assert!("0-3".parse::<PageSelection>().is_err());
+        assert!("5-2".parse::<PageSelection>().is_err());
+        assert!("1,,2".parse::<PageSelection>().is_err());
+        assert!("a-b".parse::<PageSelection>().is_err());
+    }
+
+    #[test]
+    fn test_split_pages_at_markers() {
+        let text = "<<<PAGE 3>>>\nأول\n\n<<<PAGE 5>>>\nثالث\n";
+        let pages = split_pages(text, PageRange::new(3, 5)).unwrap();
+        let texts: Vec<(usize, &str)> = pages.iter().map(|p| (p.number, p.text.as_str())).collect();
+        assert_eq!(texts, [(3, "أول"), (4, ""), (5, "ثالث")]);
+
+        let single = split_pages("نص\n", PageRange::new(7, 7)).unwrap();
+        assert_eq!(single[0].text, "نص");
+        assert!(split_pages("", PageRange::new(1, 2)).unwrap()[1]
+            .text
+            .is_empty());
+    }
+
+    #[test]
+    fn test_split_pages_rejects_invalid_markers() {
+        let range = PageRange::new(1, 3);
+        for text in [
+            "<<<PAGE 4>>>\nنص",
+            "<<<PAGE 2>>>\nنص\n<<<PAGE 1>>>\nنص",
+            "<<<PAGE 1>>>\n<<<PAGE 1>>>",
+            "نص بلا علامات",
+            "نص\n<<<PAGE 1>>>\nنص",
+        ] {
+            assert!(
+                matches!(split_pages(text, range), Err(Error::PageMarkers { .. })),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_join_pages_with_numbered_separator() {
+        let pages = [Page::new(1, "أ"), Page::new(2, "ب")];
+        assert_eq!(join_pages(&pages, "\n[{page}]\n"), "أ\n[2]\nب");
+    }
+
+    #[test]
+    fn test_page_range_display() {
+        assert_eq!(PageRange::new(3, 3).to_string(), "3");
+        assert_eq!(PageRange::new(3, 7).to_string(), "3-7");
+    }
+}
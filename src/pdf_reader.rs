@@ -1,13 +1,373 @@
-use anyhow::{Context, Result};
-use std::fs;
+use crate::error::{Error, Result};
+use lopdf::xref::XrefEntry;
+use lopdf::{Dictionary, Document, LoadOptions, Object, ObjectId};
+use memmap2::Mmap;
+use std::fs::File;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
-pub fn read_pdf(path: &str) -> Result<Vec<u8>> {
-    fs::read(path).context("Failed to read PDF file")
+/// A PDF on disk, memory-mapped so that parsing, hashing and uploading do
+/// not need a heap copy of the whole file.
+pub struct PdfSource {
+    path: PathBuf,
+    map: Mmap,
+}
+
+impl PdfSource {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens an independent, seekable reader over the file for streaming
+    /// uploads.
+    pub async fn reader(&self) -> Result<tokio::fs::File> {
+        tokio::fs::File::open(&self.path)
+            .await
+            .map_err(Error::io("Failed to open PDF file"))
+    }
+}
+
+impl Deref for PdfSource {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+pub fn read_pdf(path: &str) -> Result<PdfSource> {
+    let file = File::open(path).map_err(Error::io("Failed to read PDF file"))?;
+    // SAFETY: the mapping is read-only; like every reader of the file we
+    // rely on it not being truncated while the job runs.
+    let map = unsafe { Mmap::map(&file) }.map_err(Error::io("Failed to map PDF file"))?;
+    Ok(PdfSource {
+        path: PathBuf::from(path),
+        map,
+    })
+}
+
+/// A parsed PDF whose stream data (page images, fonts, content streams)
+/// stays in the file until a stream is read, so that parsing takes memory
+/// in proportion to the number of objects rather than to the file size.
+pub struct PdfDocument<'a> {
+    document: Document,
+    data: &'a [u8],
+    /// Start of the `%PDF-` header, from which the parser counts offsets.
+    header: usize,
+}
+
+impl Deref for PdfDocument<'_> {
+    type Target = Document;
+
+    fn deref(&self) -> &Document {
+        &self.document
+    }
+}
+
+impl PdfDocument<'_> {
+    /// Returns object `id`, with the data of a stream read from the file.
+    pub fn load_object(&self, id: ObjectId) -> Result<Object> {
+        let mut object = self
+            .document
+            .get_object(id)
+            .map_err(|e| Error::parse(format!("Invalid object {id:?}"), e))?
+            .clone();
+        if let Object::Stream(stream) = &mut object {
+            if stream.content.is_empty() {
+                stream.content = self.stream_data(id, &stream.dict)?.to_vec();
+            }
+        }
+        Ok(object)
+    }
+
+    /// Returns the decoded data of stream `id`, or its raw data when it
+    /// cannot be decoded.
+    pub fn stream_content(&self, id: ObjectId) -> Result<Vec<u8>> {
+        match self.load_object(id)? {
+            Object::Stream(stream) => Ok(stream
+                .get_plain_content()
+                .unwrap_or_else(|_| stream.content.clone())),
+            _ => Err(Error::parse(
+                format!("Invalid object {id:?}"),
+                "not a stream",
+            )),
+        }
+    }
+
+    /// Bytes the data of a stream with dictionary `dict` takes in the file,
+    /// according to its `/Length`.
+    pub fn stream_length(&self, dict: &Dictionary) -> Option<usize> {
+        let length = dict.get(b"Length").ok()?;
+        let (_, length) = self.document.dereference(length).ok()?;
+        usize::try_from(length.as_i64().ok()?).ok()
+    }
+
+    /// Finds the data of stream `id` after the `stream` keyword of its
+    /// object in the file.
+    fn stream_data(&self, id: ObjectId, dict: &Dictionary) -> Result<&[u8]> {
+        let invalid = |message: &str| Error::parse(format!("Invalid stream {id:?}"), message);
+        let Some(&XrefEntry::Normal { offset, .. }) = self.document.reference_table.get(id.0)
+        else {
+            return Ok(&[]);
+        };
+        let object = self
+            .data
+            .get(self.header + offset as usize..)
+            .ok_or_else(|| invalid("object lies beyond the end of the file"))?;
+        let keyword = object
+            .windows(7)
+            .position(|window| window.starts_with(b"stream") && matches!(window[6], b'\r' | b'\n'))
+            .ok_or_else(|| invalid("no stream keyword"))?;
+        let mut start = keyword + 6;
+        if object[start..].starts_with(b"\r\n") {
+            start += 2;
+        } else {
+            start += 1;
+        }
+        let data = &object[start..];
+
+        // Trust `/Length` when `endstream` follows it, as the parser does,
+        // and fall back to the keyword otherwise
+        if let Some(length) = self
+            .stream_length(dict)
+            .filter(|&length| length <= data.len())
+        {
+            let rest = data[length..].trim_ascii_start();
+            if rest.starts_with(b"endstream") {
+                return Ok(&data[..length]);
+            }
+        }
+        let end = data
+            .windows(9)
+            .position(|window| window == b"endstream")
+            .ok_or_else(|| invalid("no endstream keyword"))?;
+        let data = &data[..end];
+        Ok(data
+            .strip_suffix(b"\r\n")
+            .or_else(|| data.strip_suffix(b"\n"))
+            .or_else(|| data.strip_suffix(b"\r"))
+            .unwrap_or(data))
+    }
+}
+
+/// Empties every stream while the document is parsed, except object
+/// streams, which the parser unpacks itself. Leaving no start position
+/// keeps the parser from reading the data back in.
+fn leave_stream_data(id: ObjectId, object: &mut Object) -> Option<(ObjectId, Object)> {
+    if let Object::Stream(stream) = object {
+        if !stream.dict.has_type(b"ObjStm") && !stream.dict.has_type(b"XRef") {
+            stream.content = Vec::new();
+            stream.start_position = None;
+        }
+    }
+    Some((id, object.clone()))
+}
+
+/// Returns the number of pages in the document.
+///
+/// The count comes from the page tree reachable from the trailer's `/Root`,
+/// so cross-reference streams and object streams are resolved just like
+/// classic `xref` tables. Encrypted documents are rejected because Gemini
+/// cannot read their content either.
+pub fn count_pages(pdf_data: &[u8]) -> Result<usize> {
+    let document = load_document(pdf_data)?;
+    page_count(&document)
+}
+
+/// Returns the number of pages in an already parsed document.
+pub fn page_count(document: &Document) -> Result<usize> {
+    let page_count = document.get_pages().len();
+    if page_count == 0 {
+        return Err(Error::parse(
+            "Failed to parse PDF structure",
+            "page tree contains no pages",
+        ));
+    }
+    Ok(page_count)
+}
+
+/// Parses the PDF structure, rejecting encrypted documents.
+///
+/// Only the objects themselves are kept in memory; the data of each
+/// stream is read from `pdf_data` when it is needed, see [`PdfDocument`].
+pub fn load_document(pdf_data: &[u8]) -> Result<PdfDocument<'_>> {
+    let options = LoadOptions {
+        filter: Some(leave_stream_data),
+        ..LoadOptions::default()
+    };
+    let document = Document::load_mem_with_options(pdf_data, options)
+        .map_err(|e| Error::parse("Failed to parse PDF structure", e))?;
+    if document.was_encrypted() || document.trailer.get(b"Encrypt").is_ok() {
+        return Err(Error::EncryptedPdf);
+    }
+    Ok(PdfDocument {
+        document,
+        data: pdf_data,
+        header: pdf_data
+            .windows(5)
+            .position(|window| window == b"%PDF-")
+            .unwrap_or(0),
+    })
+}
+
+/// Builds a minimal, valid PDF with `pages` pages, each showing its page number.
+#[cfg(test)]
+pub(crate) fn sample_pdf(pages: usize) -> Vec<u8> {
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Object, Stream};
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let kids: Vec<Object> = (1..=pages)
+        .map(|number| {
+            let content = Content {
+                operations: vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 24.into()]),
+                    Operation::new("Td", vec![100.into(), 600.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(format!("Page {number}"))]),
+                    Operation::new("ET", vec![]),
+                ],
+            };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            })
+            .into()
+        })
+        .collect();
+
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    let mut buffer = Vec::new();
+    doc.save_to(&mut buffer).unwrap();
+    buffer
+}
+
+/// A PDF whose only font maps `A`, `B` and `C` to Arabic letters, `D` to
+/// lam-alef and `E` to an isolated meem presentation form through its
+/// `/ToUnicode` CMap, with one page per entry of `pages` showing each of
+/// its lines twice.
+#[cfg(test)]
+pub(crate) fn sample_text_layer_pdf(pages: &[&[&str]]) -> Vec<u8> {
+    use lopdf::content::Operation;
+    use lopdf::Object;
+
+    sample_content_pdf(
+        pages
+            .iter()
+            .map(|lines| {
+                let mut operations = vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                ];
+                for line in *lines {
+                    operations.push(Operation::new("Td", vec![0.into(), (-14).into()]));
+                    operations.push(Operation::new(
+                        "TJ",
+                        vec![Object::Array(vec![
+                            Object::string_literal(*line),
+                            (-400).into(),
+                            Object::string_literal(*line),
+                        ])],
+                    ));
+                }
+                operations.push(Operation::new("ET", vec![]));
+                operations
+            })
+            .collect(),
+    )
+}
+
+/// A PDF with the font of [`sample_text_layer_pdf`] as `F1` and one page
+/// per entry of `pages`, drawn by its operations.
+#[cfg(test)]
+pub(crate) fn sample_content_pdf(pages: Vec<Vec<lopdf::content::Operation>>) -> Vec<u8> {
+    use lopdf::content::Content;
+    use lopdf::{dictionary, Object, Stream};
+
+    let cmap = b"/CIDInit /ProcSet findresource begin\n\
+        begincmap\n\
+        1 begincodespacerange <00> <FF> endcodespacerange\n\
+        3 beginbfchar <20> <0020> <44> <0644 0627> <45> <FEE1> endbfchar\n\
+        2 beginbfrange <41> <42> <0628> <43> <43> [<0645>] endbfrange\n\
+        endcmap\n";
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let to_unicode = doc.add_object(Stream::new(dictionary! {}, cmap.to_vec()));
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "ArabicFont",
+        "ToUnicode" => to_unicode,
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+    let count = pages.len();
+    let kids: Vec<Object> = pages
+        .into_iter()
+        .map(|operations| {
+            let content = Content { operations };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            })
+            .into()
+        })
+        .collect();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count as i64,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+
+    let mut buffer = Vec::new();
+    doc.save_to(&mut buffer).unwrap();
+    buffer
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lopdf::dictionary;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -18,7 +378,8 @@ mod tests {
         temp_file.write_all(test_data).unwrap();
 
         let result = read_pdf(temp_file.path().to_str().unwrap()).unwrap();
-        assert_eq!(result, test_data);
+        assert_eq!(&*result, test_data);
+        assert_eq!(result.path(), temp_file.path());
     }
 
     #[test]
@@ -26,4 +387,94 @@ mod tests {
         let result = read_pdf("/nonexistent/file.pdf");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_count_pages() {
+        assert_eq!(count_pages(&sample_pdf(1)).unwrap(), 1);
+        assert_eq!(count_pages(&sample_pdf(42)).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_count_pages_encrypted() {
+        let mut doc = Document::load_mem(&sample_pdf(2)).unwrap();
+        let encrypt_id = doc.add_object(dictionary! {
+            "Filter" => "Standard",
+            "V" => 1,
+            "R" => 2,
+        });
+        doc.trailer.set("Encrypt", encrypt_id);
+        let mut buffer = Vec::new();
+        doc.save_to(&mut buffer).unwrap();
+
+        assert!(matches!(count_pages(&buffer), Err(Error::EncryptedPdf)));
+    }
+
+    /// Content stream data of every page of `pdf`, as lopdf reads it.
+    fn page_contents(pdf: &[u8]) -> Vec<Vec<u8>> {
+        let document = Document::load_mem(pdf).unwrap();
+        document
+            .get_pages()
+            .values()
+            .map(|&id| document.get_page_content(id))
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_data_is_read_on_demand() {
+        // An indirect /Length, object and xref streams, and junk before
+        // the header
+        let mut doc = Document::load_mem(&sample_pdf(3)).unwrap();
+        let page_id = doc.get_pages()[&2];
+        let content_id = doc.get_page_contents(page_id)[0];
+        let length = doc
+            .get_object(content_id)
+            .unwrap()
+            .as_stream()
+            .unwrap()
+            .content
+            .len();
+        let length_id = doc.add_object(length as i64);
+        doc.get_object_mut(content_id)
+            .unwrap()
+            .as_stream_mut()
+            .unwrap()
+            .dict
+            .set("Length", length_id);
+        let mut pdf = b"junk\n".to_vec();
+        let options = lopdf::SaveOptions::builder()
+            .use_object_streams(true)
+            .use_xref_streams(true)
+            .build();
+        doc.save_with_options(&mut pdf, options).unwrap();
+
+        let document = load_document(&pdf).unwrap();
+        let stored = document
+            .get_object(content_id)
+            .unwrap()
+            .as_stream()
+            .unwrap();
+        assert!(stored.content.is_empty());
+
+        let expected = page_contents(&pdf);
+        let contents: Vec<Vec<u8>> = document
+            .get_pages()
+            .values()
+            .map(|&id| {
+                let mut content = Vec::new();
+                for id in document.get_page_contents(id) {
+                    content.extend(document.stream_content(id).unwrap());
+                    content.push(b'\n');
+                }
+                content
+            })
+            .collect();
+        assert_eq!(contents, expected);
+        assert!(String::from_utf8_lossy(&contents[1]).contains("Page 2"));
+    }
+
+    #[test]
+    fn test_count_pages_malformed() {
+        let result = count_pages(b"test pdf content");
+        assert!(matches!(result, Err(Error::Parse { .. })));
+    }
 }
@@ -0,0 +1,263 @@
+use crate::error::{Error, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Request budgets for the Gemini API.
+///
+/// `None` (or `0` when read from the environment) disables a limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimits {
+    pub max_concurrent_requests: usize,
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u64>,
+}
+
+impl Default for RateLimits {
+    /// The free-tier quota of `gemini-2.5-flash`.
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: 2,
+            requests_per_minute: Some(10),
+            tokens_per_minute: Some(250_000),
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_concurrent_requests: Semaphore::MAX_PERMITS,
+            requests_per_minute: None,
+            tokens_per_minute: None,
+        }
+    }
+
+    /// Reads `MAX_CONCURRENT_REQUESTS`, `REQUESTS_PER_MINUTE` and
+    /// `TOKENS_PER_MINUTE`, keeping the defaults for unset variables.
+    pub fn from_env() -> Result<Self> {
+        let mut limits = Self::default();
+        if let Some(value) = env_number::<usize>("MAX_CONCURRENT_REQUESTS")? {
+            limits.max_concurrent_requests = value.max(1);
+        }
+        if let Some(value) = env_number::<u32>("REQUESTS_PER_MINUTE")? {
+            limits.requests_per_minute = (value > 0).then_some(value);
+        }
+        if let Some(value) = env_number::<u64>("TOKENS_PER_MINUTE")? {
+            limits.tokens_per_minute = (value > 0).then_some(value);
+        }
+        Ok(limits)
+    }
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::InvalidInput(format!("{name} must be a number, got '{value}'"))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// A token bucket refilled continuously at `capacity` units per minute.
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u64) -> Self {
+        Self {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until `amount` units are available (zero when they already are).
+    fn wait_time(&mut self, amount: f64) -> Duration {
+        self.refill();
+        let missing = amount.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing * 60.0 / self.capacity)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+}
+
+/// Client-side limiter enforcing [`RateLimits`] across every request made
+/// through the clients that share it.
+///
+/// Requests first wait for a concurrency slot, then until both the request
+/// and the token buckets can cover them. Token usage is estimated up front
+/// and corrected with the `usageMetadata` Gemini reports back.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    slots: Arc<Semaphore>,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    /// Tokens used by the most recent request, the best estimate for the next.
+    last_usage: Option<u64>,
+}
+
+/// Holds a concurrency slot until the request has completed.
+pub struct RatePermit {
+    _slot: OwnedSemaphorePermit,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(limits.max_concurrent_requests.max(1))),
+            buckets: Mutex::new(Buckets {
+                requests: limits
+                    .requests_per_minute
+                    .map(|rpm| Bucket::new(rpm.into())),
+                tokens: limits.tokens_per_minute.map(Bucket::new),
+                last_usage: None,
+            }),
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Token estimate for the next request: the usage of the previous one,
+    /// or `fallback` before any request has completed.
+    pub fn estimate_tokens(&self, fallback: u64) -> u64 {
+        self.buckets.lock().unwrap().last_usage.unwrap_or(fallback)
+    }
+
+    /// Waits until a request expected to use `tokens` tokens may be sent.
+    pub async fn acquire(&self, tokens: u64) -> RatePermit {
+        let slot = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("rate limiter semaphore is never closed");
+
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let wait = [
+                    buckets.requests.as_mut().map(|b| b.wait_time(1.0)),
+                    buckets.tokens.as_mut().map(|b| b.wait_time(tokens as f64)),
+                ]
+                .into_iter()
+                .flatten()
+                .max()
+                .unwrap_or(Duration::ZERO);
+
+                if wait.is_zero() {
+                    if let Some(bucket) = buckets.requests.as_mut() {
+                        bucket.take(1.0);
+                    }
+                    if let Some(bucket) = buckets.tokens.as_mut() {
+                        bucket.take(tokens as f64);
+                    }
+                }
+                wait
+            };
+
+            if wait.is_zero() {
+                return RatePermit { _slot: slot };
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Corrects the token bucket once the actual usage of a request is known.
+    pub fn record_usage(&self, estimated: u64, actual: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.last_usage = Some(actual);
+        if let Some(bucket) = buckets.tokens.as_mut() {
+            bucket.refill();
+            bucket.available =
+                (bucket.available + estimated as f64 - actual as f64).min(bucket.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_wait_time() {
+        let mut bucket = Bucket::new(60);
+        assert_eq!(bucket.wait_time(60.0), Duration::ZERO);
+        bucket.take(60.0);
+        let wait = bucket.wait_time(30.0);
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_oversized_request_is_clamped_to_capacity() {
+        let mut bucket = Bucket::new(100);
+        assert_eq!(bucket.wait_time(1_000.0), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_per_minute_are_spread_out() {
+        let limiter = RateLimiter::new(RateLimits {
+            max_concurrent_requests: 10,
+            requests_per_minute: Some(2),
+            tokens_per_minute: None,
+        });
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            drop(limiter.acquire(0).await);
+        }
+        // Two requests fit the initial budget, the third waits for a refill
+        assert!(start.elapsed() >= Duration::from_secs(29));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let limiter = RateLimiter::new(RateLimits {
+            max_concurrent_requests: 1,
+            requests_per_minute: None,
+            tokens_per_minute: None,
+        });
+
+        let first = limiter.acquire(0).await;
+        assert_eq!(limiter.slots.available_permits(), 0);
+        drop(first);
+        assert_eq!(limiter.slots.available_permits(), 1);
+    }
+
+    #[test]
+    fn test_record_usage_updates_estimate() {
+        let limiter = RateLimiter::new(RateLimits::default());
+        assert_eq!(limiter.estimate_tokens(5_000), 5_000);
+        limiter.record_usage(5_000, 12_000);
+        assert_eq!(limiter.estimate_tokens(5_000), 12_000);
+    }
+}
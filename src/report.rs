@@ -0,0 +1,631 @@
+use crate::bidi::ReversedLines;
+use crate::error::Error;
+use crate::gemini_client::UploadedFile;
+use crate::normalize::{self, HarakatCount};
+use crate::pages::PageRange;
+use crate::rate_limit::RateLimits;
+use crate::ui::VerboseUI;
+use crate::verify::PageVerification;
+use humansize::{format_size, BINARY};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Totals reported once every page range has been processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobSummary {
+    pub ranges: usize,
+    pub succeeded: usize,
+    pub failed: Vec<FailedRange>,
+    pub total_chars: usize,
+    /// Pages with lines that read backwards.
+    pub reversed: Vec<ReversedLines>,
+}
+
+/// A page range whose extraction failed after all retries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedRange {
+    pub start: usize,
+    pub end: usize,
+    pub error: String,
+    /// HTTP status of the last attempt, if there was a response.
+    pub status: Option<u16>,
+}
+
+impl FailedRange {
+    pub fn new(range: PageRange, error: &Error) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+            error: error.to_string(),
+            status: error.status().map(|status| status.as_u16()),
+        }
+    }
+
+    pub fn range(&self) -> PageRange {
+        PageRange::new(self.start, self.end)
+    }
+}
+
+/// Receives the progress of a job, see [`Config::progress`]. Every method
+/// has an empty default, so implementations only handle the events they
+/// care about.
+///
+/// Events are delivered from the tasks extracting page ranges, so they may
+/// arrive concurrently and implementations should return quickly. The
+/// sinks of the CLI write to stderr; stdout is reserved for the extracted
+/// text.
+///
+/// [`Config::progress`]: crate::Config::progress
+pub trait ProgressSink: Send + Sync {
+    fn job_started(&self, _path: &str, _size: u64) {}
+    fn page_count(&self, _total_pages: usize) {}
+    fn resume_status(&self, _completed: usize, _total: usize) {}
+    /// `local` pages are read from the PDF's text layer and `remote` pages
+    /// still go to Gemini.
+    fn text_layer_checked(&self, _local: usize, _remote: usize) {}
+    /// The job is about to upload `total_bytes` bytes.
+    fn upload_started(&self, _total_bytes: u64) {}
+    /// Bytes uploaded so far, across all uploads of the job.
+    fn upload_progress(&self, _sent: u64) {}
+    fn upload_completed(&self, _file: &UploadedFile) {}
+    fn upload_reused(&self, _file: &UploadedFile) {}
+    /// Every upload of the job is done.
+    fn uploads_finished(&self) {}
+    fn processing_started(
+        &self,
+        _ranges: &[PageRange],
+        _pages_per_chunk: usize,
+        _model: &str,
+        _rate_limits: &RateLimits,
+    ) {
+    }
+    fn range_started(&self, _range: PageRange) {}
+    /// A request failed and is retried after `delay`; `range` is `None` for
+    /// uploads.
+    fn retrying(
+        &self,
+        _what: &str,
+        _range: Option<PageRange>,
+        _attempt: u32,
+        _delay: Duration,
+        _error: &Error,
+    ) {
+    }
+    fn range_completed(&self, _range: PageRange, _chars: usize) {}
+    fn range_failed(&self, _range: PageRange, _error: &Error) {}
+    fn warning(&self, _message: &str) {}
+    fn job_finished(&self, _summary: &JobSummary) {}
+    /// Pages compared with their text layer in verification mode.
+    fn pages_verified(&self, _pages: &[PageVerification]) {}
+    /// Harakat per extracted page, unless diacritics are stripped.
+    fn harakat_counted(&self, _counts: &[HarakatCount]) {}
+    fn uploads_deleted(&self, _deleted: usize) {}
+    fn resume_hint(&self, _state_path: &Path) {}
+    /// The extracted text was written to `path`, or to stdout when `None`.
+    fn output_written(&self, _path: Option<&str>, _elapsed: Duration) {}
+}
+
+/// How the CLI reports progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Colours, emoji and progress bars.
+    #[default]
+    Pretty,
+    /// One undecorated line per event.
+    Plain,
+    /// One JSON object per event.
+    Jsonl,
+    /// Nothing but failed page ranges and warnings.
+    Quiet,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pretty" => Ok(Self::Pretty),
+            "plain" => Ok(Self::Plain),
+            "jsonl" => Ok(Self::Jsonl),
+            "quiet" => Ok(Self::Quiet),
+            other => Err(Error::InvalidInput(format!(
+                "unknown log format '{other}' (expected pretty, plain, jsonl or quiet)"
+            ))),
+        }
+    }
+}
+
+impl LogFormat {
+    /// A sink of this format writing to stderr.
+    pub fn sink(self) -> Arc<dyn ProgressSink> {
+        match self {
+            Self::Pretty => Arc::new(VerboseUI::new()),
+            Self::Plain => Arc::new(PlainReporter::new(Box::new(std::io::stderr()))),
+            Self::Jsonl => Arc::new(JsonlReporter::new(Box::new(std::io::stderr()))),
+            Self::Quiet => Arc::new(QuietReporter::new(Box::new(std::io::stderr()))),
+        }
+    }
+}
+
+/// Ignores every event; the library default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// Writes only failed page ranges and warnings, as [`PlainReporter`] does.
+pub struct QuietReporter {
+    plain: PlainReporter,
+}
+
+impl QuietReporter {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            plain: PlainReporter::new(out),
+        }
+    }
+}
+
+impl ProgressSink for QuietReporter {
+    fn range_failed(&self, range: PageRange, error: &Error) {
+        self.plain.range_failed(range, error);
+    }
+
+    fn warning(&self, message: &str) {
+        self.plain.warning(message);
+    }
+}
+
+/// Writes one undecorated line per event, suitable for log files.
+pub struct PlainReporter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl PlainReporter {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+
+    fn line(&self, text: &str) {
+        let _ = writeln!(self.out.lock().unwrap(), "{text}");
+    }
+}
+
+impl ProgressSink for PlainReporter {
+    fn job_started(&self, path: &str, size: u64) {
+        self.line(&format!("Reading {path} ({})", format_size(size, BINARY)));
+    }
+
+    fn page_count(&self, total_pages: usize) {
+        self.line(&format!("Document has {total_pages} pages"));
+    }
+
+    fn resume_status(&self, completed: usize, total: usize) {
+        self.line(&format!(
+            "Resuming: {completed} of {total} page ranges already extracted"
+        ));
+    }
+
+    fn text_layer_checked(&self, local: usize, remote: usize) {
+        self.line(&format!(
+            "Text layer: {local} pages read locally, {remote} pages need OCR"
+        ));
+    }
+
+    fn upload_started(&self, total_bytes: u64) {
+        self.line(&format!("Uploading {}", format_size(total_bytes, BINARY)));
+    }
+
+    fn upload_completed(&self, file: &UploadedFile) {
+        self.line(&format!("Uploaded {} ({})", file.name, file.uri));
+    }
+
+    fn upload_reused(&self, file: &UploadedFile) {
+        self.line(&format!("Reusing upload {} ({})", file.name, file.uri));
+    }
+
+    fn processing_started(
+        &self,
+        ranges: &[PageRange],
+        _pages_per_chunk: usize,
+        model: &str,
+        _rate_limits: &RateLimits,
+    ) {
+        self.line(&format!(
+            "Extracting {} page ranges with {model}",
+            ranges.len()
+        ));
+    }
+
+    fn retrying(
+        &self,
+        what: &str,
+        _range: Option<PageRange>,
+        attempt: u32,
+        delay: Duration,
+        error: &Error,
+    ) {
+        self.line(&format!(
+            "{what} failed, retrying in {:.1}s (attempt {attempt}): {error}",
+            delay.as_secs_f64()
+        ));
+    }
+
+    fn range_completed(&self, range: PageRange, chars: usize) {
+        self.line(&format!("Pages {range}: {chars} characters"));
+    }
+
+    fn range_failed(&self, range: PageRange, error: &Error) {
+        self.line(&format!("Pages {range} failed: {error}"));
+    }
+
+    fn warning(&self, message: &str) {
+        self.line(&format!("Warning: {message}"));
+    }
+
+    fn job_finished(&self, summary: &JobSummary) {
+        self.line(&format!(
+            "Done: {} of {} page ranges extracted, {} failed, {} characters",
+            summary.succeeded,
+            summary.ranges,
+            summary.failed.len(),
+            summary.total_chars
+        ));
+        for failed in &summary.failed {
+            self.line(&format!(
+                "Missing pages {}: {}",
+                failed.range(),
+                failed.error
+            ));
+        }
+        for page in &summary.reversed {
+            let lines: Vec<String> = page.lines.iter().map(ToString::to_string).collect();
+            self.line(&format!(
+                "{} lines on page {}: {}",
+                if page.repaired {
+                    "Repaired"
+                } else {
+                    "Reversed"
+                },
+                page.page,
+                lines.join(", ")
+            ));
+        }
+    }
+
+    fn pages_verified(&self, pages: &[PageVerification]) {
+        let flagged = pages.iter().filter(|page| page.flagged).count();
+        self.line(&format!(
+            "Verified {} pages against the text layer, {flagged} flagged",
+            pages.len()
+        ));
+        for page in pages.iter().filter(|page| page.flagged) {
+            self.line(&format!(
+                "Warning: page {} differs from its text layer (CER {:.1}%, WER {:.1}%)",
+                page.page,
+                page.cer * 100.0,
+                page.wer * 100.0
+            ));
+        }
+    }
+
+    fn harakat_counted(&self, counts: &[HarakatCount]) {
+        let total: usize = counts.iter().map(|count| count.harakat).sum();
+        self.line(&format!("Harakat: {total} on {} pages", counts.len()));
+        for page in normalize::pages_missing_harakat(counts) {
+            self.line(&format!("Warning: page {page} has no harakat"));
+        }
+    }
+
+    fn uploads_deleted(&self, deleted: usize) {
+        if deleted > 0 {
+            self.line(&format!("Deleted {deleted} uploaded file(s)"));
+        }
+    }
+
+    fn resume_hint(&self, state_path: &Path) {
+        self.line(&format!(
+            "Some page ranges failed; rerun with --resume to retry them (job state: {})",
+            state_path.display()
+        ));
+    }
+
+    fn output_written(&self, path: Option<&str>, elapsed: Duration) {
+        if let Some(path) = path {
+            self.line(&format!("Saved to {path} in {:.2}s", elapsed.as_secs_f64()));
+        }
+    }
+}
+
+/// Writes one JSON object per event, e.g.
+/// `{"event":"range_completed","time":"…","range":"1-5","chars":1234}`.
+///
+/// Upload progress is reported at most once per percent.
+pub struct JsonlReporter {
+    out: Mutex<Box<dyn Write + Send>>,
+    upload_total: AtomicU64,
+    upload_percent: AtomicU64,
+}
+
+impl JsonlReporter {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Mutex::new(out),
+            upload_total: AtomicU64::new(0),
+            upload_percent: AtomicU64::new(0),
+        }
+    }
+
+    fn emit(&self, event: &str, fields: Value) {
+        let mut object = json!({
+            "event": event,
+            "time": chrono::Utc::now().to_rfc3339(),
+        });
+        if let (Some(object), Value::Object(fields)) = (object.as_object_mut(), fields) {
+            object.extend(fields);
+        }
+        let _ = writeln!(self.out.lock().unwrap(), "{object}");
+    }
+}
+
+fn file_json(file: &UploadedFile) -> Value {
+    json!({
+        "name": file.name,
+        "uri": file.uri,
+        "size_bytes": file.size_bytes,
+        "expiration_time": file.expiration_time,
+    })
+}
+
+impl ProgressSink for JsonlReporter {
+    fn job_started(&self, path: &str, size: u64) {
+        self.emit("job_started", json!({ "path": path, "size_bytes": size }));
+    }
+
+    fn page_count(&self, total_pages: usize) {
+        self.emit("page_count", json!({ "total_pages": total_pages }));
+    }
+
+    fn resume_status(&self, completed: usize, total: usize) {
+        self.emit(
+            "resume_status",
+            json!({ "completed_ranges": completed, "total_ranges": total }),
+        );
+    }
+
+    fn text_layer_checked(&self, local: usize, remote: usize) {
+        self.emit(
+            "text_layer_checked",
+            json!({ "local_pages": local, "remote_pages": remote }),
+        );
+    }
+
+    fn upload_started(&self, total_bytes: u64) {
+        self.upload_total.store(total_bytes, Ordering::Relaxed);
+        self.upload_percent.store(0, Ordering::Relaxed);
+        self.emit("upload_started", json!({ "total_bytes": total_bytes }));
+    }
+
+    fn upload_progress(&self, sent: u64) {
+        let total = self.upload_total.load(Ordering::Relaxed).max(1);
+        let percent = (sent * 100 / total).min(100);
+        if self.upload_percent.fetch_max(percent, Ordering::Relaxed) < percent {
+            self.emit(
+                "upload_progress",
+                json!({ "sent_bytes": sent, "total_bytes": total, "percent": percent }),
+            );
+        }
+    }
+
+    fn upload_completed(&self, file: &UploadedFile) {
+        self.emit("upload_completed", file_json(file));
+    }
+
+    fn upload_reused(&self, file: &UploadedFile) {
+        self.emit("upload_reused", file_json(file));
+    }
+
+    fn processing_started(
+        &self,
+        ranges: &[PageRange],
+        pages_per_chunk: usize,
+        model: &str,
+        _rate_limits: &RateLimits,
+    ) {
+        let ranges: Vec<String> = ranges.iter().map(ToString::to_string).collect();
+        self.emit(
+            "processing_started",
+            json!({ "ranges": ranges, "pages_per_chunk": pages_per_chunk, "model": model }),
+        );
+    }
+
+    fn range_started(&self, range: PageRange) {
+        self.emit("range_started", json!({ "range": range.to_string() }));
+    }
+
+    fn retrying(
+        &self,
+        what: &str,
+        range: Option<PageRange>,
+        attempt: u32,
+        delay: Duration,
+        error: &Error,
+    ) {
+        self.emit(
+            "retrying",
+            json!({
+                "what": what,
+                "range": range.map(|range| range.to_string()),
+                "attempt": attempt,
+                "delay_seconds": delay.as_secs_f64(),
+                "status": error.status().map(|status| status.as_u16()),
+                "error": error.to_string(),
+            }),
+        );
+    }
+
+    fn range_completed(&self, range: PageRange, chars: usize) {
+        self.emit(
+            "range_completed",
+            json!({ "range": range.to_string(), "chars": chars }),
+        );
+    }
+
+    fn range_failed(&self, range: PageRange, error: &Error) {
+        self.emit(
+            "range_failed",
+            json!({
+                "range": range.to_string(),
+                "status": error.status().map(|status| status.as_u16()),
+                "error": error.to_string(),
+            }),
+        );
+    }
+
+    fn warning(&self, message: &str) {
+        self.emit("warning", json!({ "message": message }));
+    }
+
+    fn job_finished(&self, summary: &JobSummary) {
+        self.emit(
+            "job_finished",
+            json!({
+                "ranges": summary.ranges,
+                "succeeded": summary.succeeded,
+                "failed": summary.failed.len(),
+                "failed_ranges": summary.failed,
+                "total_chars": summary.total_chars,
+                "reversed_lines": summary.reversed,
+            }),
+        );
+    }
+
+    fn pages_verified(&self, pages: &[PageVerification]) {
+        let flagged: Vec<usize> = pages
+            .iter()
+            .filter(|page| page.flagged)
+            .map(|page| page.page)
+            .collect();
+        self.emit(
+            "pages_verified",
+            json!({ "pages": pages, "flagged_pages": flagged }),
+        );
+    }
+
+    fn harakat_counted(&self, counts: &[HarakatCount]) {
+        self.emit(
+            "harakat_counted",
+            json!({
+                "pages": counts,
+                "pages_missing_harakat": normalize::pages_missing_harakat(counts),
+            }),
+        );
+    }
+
+    fn uploads_deleted(&self, deleted: usize) {
+        self.emit("uploads_deleted", json!({ "deleted": deleted }));
+    }
+
+    fn resume_hint(&self, state_path: &Path) {
+        self.emit("resume_hint", json!({ "state_path": state_path }));
+    }
+
+    fn output_written(&self, path: Option<&str>, elapsed: Duration) {
+        self.emit(
+            "output_written",
+            json!({ "path": path, "elapsed_seconds": elapsed.as_secs_f64() }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer whose contents stay readable after being handed to a sink.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_jsonl_events() {
+        let buffer = Buffer::default();
+        let reporter = JsonlReporter::new(Box::new(buffer.clone()));
+        reporter.upload_started(1_000);
+        for sent in (0..=1_000).step_by(5) {
+            reporter.upload_progress(sent);
+        }
+        reporter.range_completed(PageRange::new(1, 5), 42);
+
+        let events: Vec<Value> = buffer
+            .lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events[0]["event"], "upload_started");
+        // One progress event per percent
+        assert_eq!(events.len(), 1 + 100 + 1);
+        assert_eq!(events[100]["percent"], 100);
+        assert_eq!(events[101]["event"], "range_completed");
+        assert_eq!(events[101]["range"], "1-5");
+        assert_eq!(events[101]["chars"], 42);
+    }
+
+    #[test]
+    fn test_plain_lines() {
+        let buffer = Buffer::default();
+        let reporter = PlainReporter::new(Box::new(buffer.clone()));
+        reporter.range_completed(PageRange::new(6, 10), 7);
+        reporter.uploads_deleted(0);
+        assert_eq!(buffer.lines(), ["Pages 6-10: 7 characters"]);
+    }
+
+    #[test]
+    fn test_quiet_lines() {
+        let buffer = Buffer::default();
+        let reporter = QuietReporter::new(Box::new(buffer.clone()));
+        reporter.range_completed(PageRange::new(1, 5), 7);
+        reporter.range_failed(
+            PageRange::new(6, 10),
+            &Error::InvalidInput("bad range".to_string()),
+        );
+        reporter.warning("2 pages missing");
+        assert_eq!(
+            buffer.lines(),
+            ["Pages 6-10 failed: bad range", "Warning: 2 pages missing"]
+        );
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("jsonl".parse::<LogFormat>().unwrap(), LogFormat::Jsonl);
+        assert!("fancy".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,504 @@
+use crate::bidi;
+use crate::error::{Error, Result};
+use crate::normalize::Normalization;
+use crate::pdf_reader::PdfDocument;
+use lopdf::content::Content;
+use lopdf::{Dictionary, Object};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Pages with fewer letters than this are taken to be scanned.
+const MIN_LETTERS: usize = 20;
+/// Largest share of garbled characters in usable text.
+const MAX_GARBLED_SHARE: f64 = 0.02;
+/// Smallest share of Arabic-script letters among all letters.
+const MIN_ARABIC_SHARE: f64 = 0.5;
+/// `TJ` adjustments wider than this many thousandths of an em are read as
+/// word spaces.
+const WORD_GAP: f32 = 250.0;
+/// `Td` moves along the line wider than this many ems are read as word
+/// spaces; producers that place every glyph move by about its width.
+const WORD_MOVE: f32 = 1.0;
+/// The `Tr` render mode of invisible text, as OCR layers over scans use.
+const INVISIBLE: i64 = 3;
+
+/// Why the text layer of a page cannot replace OCR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unusable {
+    /// No text, or hardly any, as on scanned pages.
+    NoText,
+    /// Unmapped glyphs or mis-decoded bytes.
+    Garbled,
+    /// Mostly letters of another script, as fonts without a usable
+    /// `/ToUnicode` map produce.
+    NotArabic,
+    /// Lines stored in visual order, which are left to OCR rather than
+    /// reordered by guesswork.
+    VisualOrder,
+}
+
+impl fmt::Display for Unusable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoText => "no text layer",
+            Self::Garbled => "garbled text layer",
+            Self::NotArabic => "too little Arabic text",
+            Self::VisualOrder => "text layer in visual order",
+        })
+    }
+}
+
+/// Decides whether text taken from a page's text layer is good enough to
+/// skip OCR.
+pub fn check_usable(text: &str) -> std::result::Result<(), Unusable> {
+    let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+    if letters < MIN_LETTERS {
+        return Err(Unusable::NoText);
+    }
+    let garbled = text.chars().filter(|&c| is_garbled(c)).count();
+    if garbled as f64 > text.chars().count() as f64 * MAX_GARBLED_SHARE {
+        return Err(Unusable::Garbled);
+    }
+    let arabic = text.chars().filter(|&c| is_arabic_letter(c)).count();
+    if (arabic as f64) < letters as f64 * MIN_ARABIC_SHARE {
+        return Err(Unusable::NotArabic);
+    }
+    Ok(())
+}
+
+/// Reads the text layer of page `number` if it can replace OCR, with
+/// presentation forms folded into base letters.
+pub fn usable_page(document: &PdfDocument, number: usize) -> std::result::Result<String, Unusable> {
+    let text = extract_page(document, number).map_err(|_| Unusable::NoText)?;
+    if !bidi::reversed_lines(&text).is_empty() {
+        return Err(Unusable::VisualOrder);
+    }
+    let text = Normalization {
+        presentation_forms: true,
+        ..Normalization::default()
+    }
+    .apply(&text);
+    check_usable(&text)?;
+    Ok(text)
+}
+
+/// Replacement and private-use characters stand for unmapped glyphs, and
+/// Latin-1 letters such as `Ø§Ù` are Arabic UTF-8 read as a legacy
+/// encoding.
+fn is_garbled(c: char) -> bool {
+    matches!(c, '\u{FFFD}' | '\u{E000}'..='\u{F8FF}' | '\u{00C0}'..='\u{00FF}')
+        || (c.is_control() && c != '\n' && c != '\t')
+}
+
+fn is_arabic_letter(c: char) -> bool {
+    c.is_alphabetic()
+        && matches!(c, '\u{0600}'..='\u{06FF}' | '\u{0750}'..='\u{077F}' | '\u{FB50}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+/// Character codes of a font mapped to Unicode, from a `/ToUnicode` CMap.
+#[derive(Debug, Default)]
+struct ToUnicode {
+    /// Bytes per character code, from the code space range.
+    code_bytes: usize,
+    map: BTreeMap<u32, String>,
+}
+
+impl ToUnicode {
+    /// Reads the `bfchar` and `bfrange` mappings of a CMap; other
+    /// operators are skipped.
+    fn parse(data: &[u8]) -> Self {
+        let tokens = cmap_tokens(data);
+        let mut cmap = Self::default();
+        let mut index = 0;
+        while index < tokens.len() {
+            match &tokens[index] {
+                CMapToken::Keyword(keyword) if keyword == "begincodespacerange" => {
+                    if let Some(CMapToken::Hex(low)) = tokens.get(index + 1) {
+                        cmap.code_bytes = low.len().max(1);
+                    }
+                }
+                CMapToken::Keyword(keyword) if keyword == "beginbfchar" => {
+                    index += 1;
+                    while let [CMapToken::Hex(code), CMapToken::Hex(target), ..] = &tokens[index..]
+                    {
+                        cmap.map.insert(code_value(code), utf16_string(target));
+                        index += 2;
+                    }
+                    continue;
+                }
+                CMapToken::Keyword(keyword) if keyword == "beginbfrange" => {
+                    index += 1;
+                    while let [CMapToken::Hex(low), CMapToken::Hex(high), target, ..] =
+                        &tokens[index..]
+                    {
+                        let (low, high) = (code_value(low), code_value(high));
+                        for (offset, code) in (low..=high.min(low + 0xFFFF)).enumerate() {
+                            let text = match target {
+                                CMapToken::Hex(start) => {
+                                    // The last UTF-16 unit counts up through the range
+                                    let mut units = utf16_units(start);
+                                    if let Some(last) = units.last_mut() {
+                                        *last = last.wrapping_add(offset as u16);
+                                    }
+                                    String::from_utf16_lossy(&units)
+                                }
+                                CMapToken::Array(targets) => match targets.get(offset) {
+                                    Some(target) => utf16_string(target),
+                                    None => break,
+                                },
+                                CMapToken::Keyword(_) => break,
+                            };
+                            cmap.map.insert(code, text);
+                        }
+                        index += 3;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+        cmap
+    }
+}
+
+enum CMapToken {
+    Hex(Vec<u8>),
+    Array(Vec<Vec<u8>>),
+    Keyword(String),
+}
+
+fn cmap_tokens(data: &[u8]) -> Vec<CMapToken> {
+    let mut tokens = Vec::new();
+    let mut array: Option<Vec<Vec<u8>>> = None;
+    let mut index = 0;
+    while index < data.len() {
+        match data[index] {
+            b'<' if data.get(index + 1) != Some(&b'<') => {
+                let end = data[index..]
+                    .iter()
+                    .position(|&b| b == b'>')
+                    .map_or(data.len(), |end| index + end);
+                let hex = parse_hex(&data[index + 1..end]);
+                match array.as_mut() {
+                    Some(array) => array.push(hex),
+                    None => tokens.push(CMapToken::Hex(hex)),
+                }
+                index = end + 1;
+            }
+            b'[' => {
+                array = Some(Vec::new());
+                index += 1;
+            }
+            b']' => {
+                tokens.push(CMapToken::Array(array.take().unwrap_or_default()));
+                index += 1;
+            }
+            b'%' => {
+                while index < data.len() && data[index] != b'\n' && data[index] != b'\r' {
+                    index += 1;
+                }
+            }
+            b if b.is_ascii_alphabetic() => {
+                let start = index;
+                while index < data.len() && data[index].is_ascii_alphanumeric() {
+                    index += 1;
+                }
+                tokens.push(CMapToken::Keyword(
+                    String::from_utf8_lossy(&data[start..index]).into_owned(),
+                ));
+            }
+            _ => index += 1,
+        }
+    }
+    tokens
+}
+
+fn parse_hex(digits: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = digits
+        .iter()
+        .filter_map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+fn code_value(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |value, &b| value << 8 | b as u32)
+}
+
+fn utf16_units(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+        .collect()
+}
+
+fn utf16_string(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(&utf16_units(bytes))
+}
+
+/// How the strings shown with a font are turned into text.
+struct FontDecoder {
+    to_unicode: Option<ToUnicode>,
+    /// Composite (`Type0`) fonts use two-byte codes unless their CMap says
+    /// otherwise.
+    composite: bool,
+}
+
+impl FontDecoder {
+    fn new(document: &PdfDocument, font: &Dictionary) -> Self {
+        let to_unicode = font
+            .get(b"ToUnicode")
+            .and_then(Object::as_reference)
+            .ok()
+            .and_then(|id| document.stream_content(id).ok())
+            .map(|data| ToUnicode::parse(&data));
+        let composite = font
+            .get(b"Subtype")
+            .and_then(Object::as_name)
+            .is_ok_and(|subtype| subtype == b"Type0");
+        Self {
+            to_unicode,
+            composite,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        let width = match &self.to_unicode {
+            Some(cmap) if cmap.code_bytes > 0 => cmap.code_bytes,
+            _ if self.composite => 2,
+            _ => 1,
+        };
+        bytes
+            .chunks(width)
+            .map(|code| match &self.to_unicode {
+                Some(cmap) => cmap
+                    .map
+                    .get(&code_value(code))
+                    .cloned()
+                    .unwrap_or_else(|| '\u{FFFD}'.to_string()),
+                // Without a map, simple fonts mostly use a Latin encoding
+                None if !self.composite => (code[0] as char).to_string(),
+                None => '\u{FFFD}'.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Reads the text layer of page `number` from its content streams, with
+/// a line break wherever the text moves to a new line. Invisible text is
+/// skipped.
+///
+/// Text is returned in the order it is drawn, which for some PDFs is
+/// visual rather than logical order, see [`crate::bidi`].
+pub fn extract_page(document: &PdfDocument, number: usize) -> Result<String> {
+    let pages = document.get_pages();
+    let page_id = *pages.get(&(number as u32)).ok_or(Error::PageOutOfRange {
+        page: number,
+        total_pages: pages.len(),
+    })?;
+    let fonts = document
+        .get_page_fonts(page_id)
+        .map_err(|e| Error::parse(format!("Failed to read fonts of page {number}"), e))?;
+    let mut data = Vec::new();
+    for id in document.get_page_contents(page_id) {
+        data.extend(document.stream_content(id)?);
+        data.push(b'\n');
+    }
+    let content = Content::decode(&data)
+        .map_err(|e| Error::parse(format!("Failed to read content of page {number}"), e))?;
+
+    let mut decoders: HashMap<Vec<u8>, FontDecoder> = HashMap::new();
+    let mut font: Option<Vec<u8>> = None;
+    let mut font_size: f32 = 0.0;
+    let mut render_mode: i64 = 0;
+    let mut saved_modes: Vec<i64> = Vec::new();
+    let mut text = String::new();
+    let mut line_y: Option<f32> = None;
+
+    let new_line = |text: &mut String| {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    };
+
+    for operation in &content.operations {
+        let operands = &operation.operands;
+        let visible = render_mode != INVISIBLE;
+        let mut show = |text: &mut String, bytes: &[u8]| {
+            if let Some(name) = font.as_ref().filter(|_| visible) {
+                let decoder =
+                    decoders
+                        .entry(name.clone())
+                        .or_insert_with(|| match fonts.get(name) {
+                            Some(dictionary) => FontDecoder::new(document, dictionary),
+                            None => FontDecoder {
+                                to_unicode: None,
+                                composite: false,
+                            },
+                        });
+                text.push_str(&decoder.decode(bytes));
+            }
+        };
+
+        match operation.operator.as_str() {
+            "Tf" => {
+                font = operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .map(<[u8]>::to_vec);
+                font_size = operands
+                    .get(1)
+                    .and_then(|size| size.as_float().ok())
+                    .unwrap_or(0.0);
+            }
+            "Tr" => {
+                render_mode = operands
+                    .first()
+                    .and_then(|mode| mode.as_i64().ok())
+                    .unwrap_or(0);
+            }
+            // The render mode is part of the graphics state
+            "q" => saved_modes.push(render_mode),
+            "Q" => render_mode = saved_modes.pop().unwrap_or(render_mode),
+            "Tj" => {
+                if let Some(Ok(bytes)) = operands.first().map(Object::as_str) {
+                    show(&mut text, bytes);
+                }
+            }
+            "'" | "\"" => {
+                new_line(&mut text);
+                if let Some(Ok(bytes)) = operands.last().map(Object::as_str) {
+                    show(&mut text, bytes);
+                }
+            }
+            "TJ" => {
+                let Some(Ok(items)) = operands.first().map(Object::as_array) else {
+                    continue;
+                };
+                for item in items {
+                    match item {
+                        Object::String(bytes, _) => show(&mut text, bytes),
+                        _ => {
+                            let gap = item.as_float().unwrap_or(0.0);
+                            if visible && gap.abs() > WORD_GAP && !text.ends_with([' ', '\n']) {
+                                text.push(' ');
+                            }
+                        }
+                    }
+                }
+            }
+            "Td" | "TD" => {
+                let offset = |index: usize| {
+                    operands
+                        .get(index)
+                        .and_then(|offset| offset.as_float().ok())
+                        .unwrap_or(0.0)
+                };
+                let (x, y) = (offset(0), offset(1));
+                if y != 0.0 {
+                    new_line(&mut text);
+                } else if x.abs() > WORD_MOVE * font_size
+                    && !text.ends_with([' ', '\n'])
+                    && !text.is_empty()
+                {
+                    text.push(' ');
+                }
+            }
+            "T*" => new_line(&mut text),
+            "Tm" => {
+                let y = operands.get(5).and_then(|y| y.as_float().ok());
+                if y.is_some() && line_y.is_some() && y != line_y {
+                    new_line(&mut text);
+                }
+                line_y = y;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf_reader;
+
+    #[test]
+    fn test_extract_page_decodes_through_to_unicode() {
+        let pdf = pdf_reader::sample_text_layer_pdf(&[&["AB C", "DC"], &[]]);
+        let document = pdf_reader::load_document(&pdf).unwrap();
+        assert_eq!(extract_page(&document, 1).unwrap(), "بة م بة م\nلام لام");
+        assert_eq!(extract_page(&document, 2).unwrap(), "");
+        assert!(extract_page(&document, 3).is_err());
+    }
+
+    #[test]
+    fn test_extract_page_joins_placed_glyphs_and_skips_invisible_text() {
+        use lopdf::content::Operation;
+
+        let glyph = |operations: &mut Vec<Operation>, x: i64, code: &str| {
+            operations.push(Operation::new("Td", vec![x.into(), 0.into()]));
+            operations.push(Operation::new("Tj", vec![Object::string_literal(code)]));
+        };
+        let mut operations = vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), 12.into()]),
+        ];
+        glyph(&mut operations, 100, "A");
+        glyph(&mut operations, -6, "B");
+        glyph(&mut operations, -30, "C");
+        operations.extend([
+            Operation::new("q", vec![]),
+            Operation::new("Tr", vec![3.into()]),
+            Operation::new("Tj", vec![Object::string_literal("CCC")]),
+            Operation::new("Q", vec![]),
+            Operation::new("Tj", vec![Object::string_literal("A")]),
+            Operation::new("ET", vec![]),
+        ]);
+        let pdf = pdf_reader::sample_content_pdf(vec![operations]);
+        let document = pdf_reader::load_document(&pdf).unwrap();
+        assert_eq!(extract_page(&document, 1).unwrap(), "بة مب");
+    }
+
+    #[test]
+    fn test_usable_page_folds_forms_and_rejects_visual_order() {
+        let pdf = pdf_reader::sample_text_layer_pdf(&[
+            &["CAB CAB CAB CAB CAB E"],
+            &["BAC BAC BAC BAC BAC"],
+        ]);
+        let document = pdf_reader::load_document(&pdf).unwrap();
+        let text = usable_page(&document, 1).unwrap();
+        assert!(text.ends_with("مبة م"));
+        assert_eq!(usable_page(&document, 2), Err(Unusable::VisualOrder));
+    }
+
+    #[test]
+    fn test_check_usable() {
+        let arabic = "هذا نص عربي مستخرج من طبقة النص في الملف";
+        assert_eq!(check_usable(arabic), Ok(()));
+        assert_eq!(check_usable("Page 1"), Err(Unusable::NoText));
+        assert_eq!(
+            check_usable(&format!("{arabic} \u{FFFD}\u{FFFD}")),
+            Err(Unusable::Garbled)
+        );
+        assert_eq!(
+            check_usable("Ø§Ù„Ø¹Ø±Ø¨ÙŠØ© Ø§Ù„Ø¹Ø±Ø¨ÙŠØ© Ø§Ù„Ø¹Ø±Ø¨ÙŠØ©"),
+            Err(Unusable::Garbled)
+        );
+        assert_eq!(
+            check_usable("This page is written in English with a word of عربي"),
+            Err(Unusable::NotArabic)
+        );
+    }
+}
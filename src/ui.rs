@@ -1,8 +1,19 @@
+use crate::bidi::ReversedLines;
+use crate::error::Error;
+use crate::gemini_client::UploadedFile;
+use crate::normalize::{self, HarakatCount};
+use crate::pages::PageRange;
+use crate::rate_limit::RateLimits;
+use crate::report::{FailedRange, JobSummary, ProgressSink};
+use crate::verify::PageVerification;
 use chrono::Local;
 use colored::*;
 use console::{style, Emoji};
 use humansize::{format_size, BINARY};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
 use std::time::Duration;
 
 // Emojis for different states
@@ -13,7 +24,6 @@ static HOURGLASS: Emoji<'_, '_> = Emoji("⏳ ", "");
 static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", "");
 static ROCKET: Emoji<'_, '_> = Emoji("🚀 ", "");
 static PAPER: Emoji<'_, '_> = Emoji("📄 ", "");
-static GLOBE: Emoji<'_, '_> = Emoji("🌍 ", "");
 static PACKAGE: Emoji<'_, '_> = Emoji("📦 ", "");
 static CHECKMARK: Emoji<'_, '_> = Emoji("✅ ", "");
 static WARNING: Emoji<'_, '_> = Emoji("⚠️  ", "");
@@ -22,181 +32,284 @@ static BRAIN: Emoji<'_, '_> = Emoji("🧠 ", "");
 static MAGIC: Emoji<'_, '_> = Emoji("🪄 ", "");
 static BOOK: Emoji<'_, '_> = Emoji("📚 ", "");
 
+/// The colourful terminal reporter behind `--log-format pretty`.
+///
+/// Diagnostics and progress bars go to stderr; only listings requested
+/// explicitly, such as [`VerboseUI::print_remote_files`], go to stdout.
 pub struct VerboseUI {
     multi_progress: MultiProgress,
+    upload_bar: Mutex<Option<ProgressBar>>,
+    range_bars: Mutex<BTreeMap<PageRange, ProgressBar>>,
 }
 
 impl VerboseUI {
     pub fn new() -> Self {
         Self {
             multi_progress: MultiProgress::new(),
+            upload_bar: Mutex::new(None),
+            range_bars: Mutex::new(BTreeMap::new()),
         }
     }
 
     pub fn print_banner(&self) {
-        println!(
+        eprintln!(
             "\n{}",
             "╔═══════════════════════════════════════════════════════════════╗".bright_cyan()
         );
-        println!(
+        eprintln!(
             "{}",
             "║                                                               ║".bright_cyan()
         );
-        println!(
+        eprintln!(
             "{}",
             "║          🌙  Arabic PDF to Text Converter  🌙                 ║".bright_cyan()
         );
-        println!(
+        eprintln!(
             "{}",
             "║                                                               ║".bright_cyan()
         );
-        println!(
+        eprintln!(
             "{}",
             "║              Powered by Google Gemini AI                      ║".bright_cyan()
         );
-        println!(
+        eprintln!(
             "{}",
             "║                                                               ║".bright_cyan()
         );
-        println!(
+        eprintln!(
             "{}",
             "╚═══════════════════════════════════════════════════════════════╝".bright_cyan()
         );
-        println!();
+        eprintln!();
     }
 
     pub fn print_file_info(&self, path: &str, size: usize) {
         let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-        println!(
+        eprintln!(
             "{} {}",
             style("[INFO]").green().bold(),
             style(&timestamp).dim()
         );
 
-        println!(
+        eprintln!(
             "\n{} {}",
             LOOKING_GLASS,
             style("Analyzing PDF file...").cyan().bold()
         );
-        std::thread::sleep(Duration::from_millis(500));
 
-        println!(
+        eprintln!(
             "  {} {}",
             style("►").yellow(),
             style("File path:").white().bold()
         );
-        println!("    {}", style(path).green());
+        eprintln!("    {}", style(path).green());
 
-        println!(
+        eprintln!(
             "  {} {}",
             style("►").yellow(),
             style("File size:").white().bold()
         );
         let size_str = format_size(size, BINARY);
-        println!(
+        eprintln!(
             "    {} ({} bytes)",
             style(&size_str).green(),
             style(size.to_string()).dim()
         );
 
-        println!(
+        eprintln!(
             "  {} {}",
             style("►").yellow(),
             style("MIME type:").white().bold()
         );
-        println!("    {}", style("application/pdf").green());
+        eprintln!("    {}", style("application/pdf").green());
 
-        println!(
+        eprintln!(
             "  {} {}",
             style("►").yellow(),
             style("Estimated processing time:").white().bold()
         );
         let est_time = (size as f64 / 1_000_000.0 * 30.0).max(60.0) as u64;
-        println!("    {} seconds", style(est_time.to_string()).green());
+        eprintln!("    {} seconds", style(est_time.to_string()).green());
+
+        eprintln!("\n{}", style("─".repeat(65)).dim());
+    }
+
+    pub fn print_page_count(&self, total_pages: usize) {
+        eprintln!(
+            "\n{} {}",
+            PAPER,
+            style(format!("Document contains {total_pages} pages"))
+                .cyan()
+                .bold()
+        );
+        eprintln!("\n{}", style("─".repeat(65)).dim());
+    }
+
+    pub fn print_text_layer_status(&self, local: usize, remote: usize) {
+        eprintln!(
+            "\n{} {}",
+            PAPER,
+            style(format!(
+                "Text layer: {local} pages read locally, {remote} pages need OCR"
+            ))
+            .cyan()
+            .bold()
+        );
+    }
 
-        println!("\n{}", style("─".repeat(65)).dim());
+    pub fn print_resume_status(&self, completed: usize, total: usize) {
+        eprintln!(
+            "\n{} {}",
+            HOURGLASS,
+            style(format!(
+                "Resuming job: {completed} of {total} page ranges already extracted"
+            ))
+            .cyan()
+            .bold()
+        );
+        eprintln!("\n{}", style("─".repeat(65)).dim());
+    }
+
+    pub fn print_resume_hint(&self, state_path: &Path) {
+        eprintln!(
+            "\n{} {}",
+            WARNING,
+            style("Some page ranges failed; rerun with --resume to retry only those.").yellow()
+        );
+        eprintln!(
+            "  {} Job state: {}",
+            style("►").yellow(),
+            style(state_path.display()).dim()
+        );
+    }
+
+    pub fn print_warning(&self, message: &str) {
+        eprintln!("{} {}", WARNING, style(message).yellow());
+    }
+
+    pub fn print_cleanup_summary(&self, deleted: usize) {
+        if deleted > 0 {
+            eprintln!(
+                "\n{} {}",
+                CHECKMARK,
+                style(format!("Deleted {deleted} uploaded file(s) from Gemini")).green()
+            );
+        }
+    }
+
+    pub fn print_remote_files(&self, files: &[UploadedFile]) {
+        if files.is_empty() {
+            println!("{} {}", PACKAGE, style("No uploaded files").dim());
+            return;
+        }
+        println!(
+            "{} {}",
+            PACKAGE,
+            style(format!("{} uploaded file(s)", files.len()))
+                .cyan()
+                .bold()
+        );
+        for file in files {
+            println!(
+                "  {} {} {} {} {}",
+                style("►").yellow(),
+                style(&file.name).green(),
+                style(file.display_name.as_deref().unwrap_or("-")).white(),
+                style(
+                    file.size_bytes
+                        .map_or_else(|| "-".to_string(), |size| format_size(size, BINARY))
+                )
+                .cyan(),
+                style(file.state).dim()
+            );
+            if let Some(expires) = file.expiration_time {
+                println!(
+                    "      expires {}",
+                    style(expires.with_timezone(&Local).format("%Y-%m-%d %H:%M")).dim()
+                );
+            }
+        }
     }
 
     pub fn create_upload_progress(&self, total_size: u64) -> ProgressBar {
         let pb = self.multi_progress.add(ProgressBar::new(total_size));
         pb.set_style(
             ProgressStyle::default_bar()
-                .template("{{prefix}}\n{{bar:40.cyan/blue}} {{percent:>3}}% {{bytes}}/{{total_bytes}} {{msg}}")
+                .template("{prefix}\n{bar:40.cyan/blue} {percent:>3}% {bytes}/{total_bytes} {bytes_per_sec} {msg}")
                 .unwrap()
                 .progress_chars("█▓▒░")
         );
         pb.set_prefix(format!(
             "{} {}",
             TRUCK,
-            style("Uploading PDF to Google Cloud...").cyan().bold()
+            style("Uploading PDF to Gemini...").cyan().bold()
         ));
         pb
     }
 
-    pub fn print_upload_start(&self) {
-        println!(
+    pub fn print_upload_start(&self, total_bytes: u64) {
+        eprintln!(
             "\n{} {}",
             ROCKET,
-            style("INITIALIZING UPLOAD SEQUENCE")
-                .yellow()
-                .bold()
-                .blink()
+            style("UPLOADING TO THE GEMINI FILES API").yellow().bold()
         );
-        println!("{}", style("━".repeat(65)).yellow());
-
-        println!(
-            "\n{} {}",
-            GLOBE,
-            style("Establishing secure connection to Google Cloud Platform...").cyan()
-        );
-        std::thread::sleep(Duration::from_millis(800));
-        println!(
+        eprintln!("{}", style("━".repeat(65)).yellow());
+        eprintln!(
             "  {} {}",
-            style("✓").green(),
-            style("TLS 1.3 connection established").green()
+            style("►").yellow(),
+            style(format!(
+                "Resumable upload of {}",
+                format_size(total_bytes, BINARY)
+            ))
+            .cyan()
         );
+    }
 
-        println!(
+    pub fn print_upload_reused(&self, file_name: &str, file_uri: &str) {
+        eprintln!(
             "\n{} {}",
-            PACKAGE,
-            style("Preparing file for resumable upload protocol...").cyan()
-        );
-        std::thread::sleep(Duration::from_millis(600));
-        println!(
-            "  {} {}",
-            style("✓").green(),
-            style("File checksum calculated").green()
-        );
-        println!(
-            "  {} {}",
-            style("✓").green(),
-            style("Upload session initialized").green()
+            CHECKMARK,
+            style(format!("Reusing identical upload {file_name}"))
+                .green()
+                .bold()
         );
+        eprintln!("    {}", style(file_uri).green().underlined());
     }
 
-    pub fn print_upload_complete(&self, file_uri: &str) {
-        println!(
+    pub fn print_upload_complete(&self, file: &UploadedFile) {
+        eprintln!(
             "\n{} {}",
             CHECKMARK,
             style("UPLOAD COMPLETED SUCCESSFULLY!").green().bold()
         );
-        println!(
+        eprintln!(
             "  {} {}",
             style("►").yellow(),
             style("File URI:").white().bold()
         );
-        println!("    {}", style(file_uri).green().underlined());
-        println!(
-            "  {} {}",
-            style("►").yellow(),
-            style("Storage location:").white().bold()
-        );
-        println!("    {}", style("Google Cloud Storage (us-central1)").dim());
-        println!("\n{}", style("─".repeat(65)).dim());
+        eprintln!("    {}", style(&file.uri).green().underlined());
+        if let Some(expires) = file.expiration_time {
+            eprintln!(
+                "  {} {}",
+                style("►").yellow(),
+                style("Expires:").white().bold()
+            );
+            eprintln!(
+                "    {}",
+                style(expires.with_timezone(&Local).format("%Y-%m-%d %H:%M")).dim()
+            );
+        }
+        eprintln!("\n{}", style("─".repeat(65)).dim());
     }
 
-    pub fn print_processing_start(&self, num_chunks: usize, pages_per_chunk: usize) {
-        println!(
+    pub fn print_processing_start(
+        &self,
+        num_chunks: usize,
+        pages_per_chunk: usize,
+        model: &str,
+        rate_limits: &RateLimits,
+    ) {
+        eprintln!(
             "\n{} {}",
             BRAIN,
             style("INITIATING AI PROCESSING ENGINE")
@@ -204,56 +317,58 @@ impl VerboseUI {
                 .bold()
                 .blink()
         );
-        println!("{}", style("━".repeat(65)).magenta());
+        eprintln!("{}", style("━".repeat(65)).magenta());
 
-        println!(
+        eprintln!(
             "\n{} {}",
             MAGIC,
             style("Gemini AI Model Configuration:").cyan().bold()
         );
-        println!(
-            "  {} Model: {}",
-            style("•").yellow(),
-            style("gemini-2.5-flash").green()
-        );
-        println!(
-            "  {} Temperature: {}",
-            style("•").yellow(),
-            style("0.1 (High precision)").green()
-        );
-        println!(
-            "  {} Token limit: {}",
-            style("•").yellow(),
-            style("8192").green()
-        );
+        eprintln!("  {} Model: {}", style("•").yellow(), style(model).green());
 
-        println!(
+        eprintln!(
             "\n{} {}",
             BOOK,
             style("Document Analysis Plan:").cyan().bold()
         );
-        println!(
+        eprintln!(
             "  {} Total chunks: {}",
             style("•").yellow(),
             style(num_chunks.to_string()).green().bold()
         );
-        println!(
+        eprintln!(
             "  {} Pages per chunk: {}",
             style("•").yellow(),
             style(pages_per_chunk.to_string()).green()
         );
-        println!(
+        eprintln!(
             "  {} Parallel workers: {}",
             style("•").yellow(),
-            style("2").green()
+            style(rate_limits.max_concurrent_requests.to_string()).green()
         );
-        println!(
+        let describe = |limit: Option<String>| limit.unwrap_or_else(|| "unlimited".to_string());
+        eprintln!(
             "  {} Rate limit: {}",
             style("•").yellow(),
-            style("10 requests/minute").yellow()
+            style(describe(
+                rate_limits
+                    .requests_per_minute
+                    .map(|rpm| format!("{rpm} requests/minute"))
+            ))
+            .yellow()
+        );
+        eprintln!(
+            "  {} Token budget: {}",
+            style("•").yellow(),
+            style(describe(
+                rate_limits
+                    .tokens_per_minute
+                    .map(|tpm| format!("{tpm} tokens/minute"))
+            ))
+            .yellow()
         );
 
-        println!("\n{}", style("─".repeat(65)).dim());
+        eprintln!("\n{}", style("─".repeat(65)).dim());
     }
 
     pub fn create_chunk_progress(&self, start_page: usize, end_page: usize) -> ProgressBar {
@@ -291,131 +406,335 @@ impl VerboseUI {
         }
     }
 
-    pub fn print_rate_limit_warning(&self, chunk: usize, wait_time: u64) {
-        println!(
+    pub fn print_retry_warning(&self, what: &str, attempt: u32, delay: Duration, reason: &str) {
+        eprintln!(
             "\n{} {}",
             WARNING,
-            style("RATE LIMIT DETECTED!").yellow().bold().blink()
+            style(format!("{what} failed, retrying (attempt {attempt})"))
+                .yellow()
+                .bold()
         );
-        println!("  {} Chunk {} hit API rate limit", style("►").red(), chunk);
-        println!("  {} Implementing exponential backoff...", style("►").red());
-        println!(
-            "  {} Waiting {} seconds before retry",
+        eprintln!("  {} {}", style("►").red(), style(reason).dim());
+        eprintln!(
+            "  {} Backing off for {:.1} seconds",
             style("►").red(),
-            wait_time
+            delay.as_secs_f64()
         );
-
-        let pb = self.multi_progress.add(ProgressBar::new(wait_time));
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("  Cooldown: [{bar:30.yellow/blue}] {pos}/{len}s")
-                .unwrap(),
-        );
-
-        for i in 0..wait_time {
-            pb.set_position(i);
-            std::thread::sleep(Duration::from_secs(1));
-        }
-        pb.finish_and_clear();
-
-        println!("  {} Resuming processing...", style("✓").green());
     }
 
-    pub fn print_extraction_stats(&self, chunk_num: usize, text_length: usize) {
-        println!(
+    pub fn print_extraction_stats(&self, range: PageRange, text_length: usize) {
+        eprintln!(
             "\n{} {} {}",
             SPARKLE,
-            style(format!("Chunk {chunk_num} extraction complete:"))
+            style(format!("Pages {range} extraction complete:"))
                 .green()
                 .bold(),
             SPARKLE
         );
-        println!(
+        eprintln!(
             "  {} Characters extracted: {}",
             style("►").yellow(),
             style(text_length.to_string()).green().bold()
         );
-        println!(
+        eprintln!(
             "  {} Estimated words: {}",
             style("►").yellow(),
             style((text_length / 5).to_string()).green()
         );
-        println!(
-            "  {} Processing time: {}ms",
-            style("►").yellow(),
-            style("2341").dim()
-        );
     }
 
     pub fn print_final_summary(
         &self,
-        total_pages: usize,
+        total_ranges: usize,
         success_count: usize,
-        fail_count: usize,
+        failed: &[FailedRange],
         total_chars: usize,
+        reversed: &[ReversedLines],
     ) {
-        println!("\n{}", style("═".repeat(65)).cyan().bright());
-        println!(
+        eprintln!("\n{}", style("═".repeat(65)).cyan().bright());
+        eprintln!(
             "{} {}",
             CHECKMARK,
             style("PROCESSING COMPLETE!").green().bold().underlined()
         );
-        println!("{}", style("═".repeat(65)).cyan().bright());
+        eprintln!("{}", style("═".repeat(65)).cyan().bright());
 
-        println!("\n{} {}", PAPER, style("Final Statistics:").cyan().bold());
+        eprintln!("\n{} {}", PAPER, style("Final Statistics:").cyan().bold());
 
-        let success_rate = (success_count as f64 / total_pages as f64 * 100.0) as u32;
-        println!(
-            "  {} Total pages processed: {}",
+        let success_rate = (success_count as f64 / total_ranges as f64 * 100.0) as u32;
+        eprintln!(
+            "  {} Page ranges processed: {}",
             style("📊").cyan(),
-            style(total_pages.to_string()).white().bold()
+            style(total_ranges.to_string()).white().bold()
         );
-        println!(
+        eprintln!(
             "  {} Successful extractions: {} ({}%)",
             style("✅").green(),
             style(success_count.to_string()).green().bold(),
             style(success_rate.to_string()).green()
         );
 
-        if fail_count > 0 {
-            println!(
+        if !failed.is_empty() {
+            eprintln!(
                 "  {} Failed extractions: {}",
                 style("❌").red(),
-                style(fail_count.to_string()).red().bold()
+                style(failed.len().to_string()).red().bold()
             );
+            for range in failed {
+                eprintln!(
+                    "     {} {}",
+                    style(format!("Pages {}:", range.range())).red(),
+                    style(&range.error).dim()
+                );
+            }
         }
 
-        println!(
+        if !reversed.is_empty() {
+            eprintln!(
+                "  {} Pages with reversed lines: {}",
+                style("⇄").yellow(),
+                style(reversed.len().to_string()).yellow().bold()
+            );
+            for page in reversed {
+                let lines: Vec<String> = page.lines.iter().map(ToString::to_string).collect();
+                eprintln!(
+                    "     {} {}{}",
+                    style(format!("Page {}:", page.page)).yellow(),
+                    style(format!("lines {}", lines.join(", "))).dim(),
+                    if page.repaired { " (repaired)" } else { "" }
+                );
+            }
+        }
+
+        eprintln!(
             "  {} Total characters extracted: {}",
             style("📝").cyan(),
             style(total_chars.to_string()).white().bold()
         );
-        println!(
+        eprintln!(
             "  {} Estimated words: {}",
             style("📖").cyan(),
             style((total_chars / 5).to_string()).white().bold()
         );
 
-        println!(
+        eprintln!(
             "\n{} {}",
             style("🎉").cyan(),
             style("Thank you for using Arabic PDF to Text Converter!")
                 .cyan()
                 .bold()
         );
-        println!("{}", style("═".repeat(65)).cyan().bright());
+        eprintln!("{}", style("═".repeat(65)).cyan().bright());
+    }
+
+    pub fn print_verification_report(&self, pages: &[PageVerification]) {
+        let flagged: Vec<&PageVerification> = pages.iter().filter(|page| page.flagged).collect();
+        eprintln!(
+            "\n{} Verified {} pages against the text layer: {}",
+            style("🔍").cyan(),
+            style(pages.len().to_string()).white().bold(),
+            if flagged.is_empty() {
+                style("all match".to_string()).green()
+            } else {
+                style(format!("{} flagged", flagged.len())).yellow().bold()
+            }
+        );
+        for page in flagged {
+            eprintln!(
+                "     {} {}",
+                style(format!("Page {}:", page.page)).yellow(),
+                style(format!(
+                    "CER {:.1}%, WER {:.1}%",
+                    page.cer * 100.0,
+                    page.wer * 100.0
+                ))
+                .dim()
+            );
+        }
+    }
+
+    pub fn print_harakat_report(&self, counts: &[HarakatCount]) {
+        let harakat: usize = counts.iter().map(|count| count.harakat).sum();
+        let letters: usize = counts.iter().map(|count| count.letters).sum();
+        eprintln!(
+            "\n{} Harakat: {} on {} letters ({:.1} per 100)",
+            style("🔤").cyan(),
+            style(harakat.to_string()).white().bold(),
+            letters,
+            harakat as f64 * 100.0 / letters.max(1) as f64
+        );
+        let missing = normalize::pages_missing_harakat(counts);
+        if !missing.is_empty() {
+            let pages: Vec<String> = missing.iter().map(ToString::to_string).collect();
+            self.print_warning(&format!(
+                "No harakat on page(s) {}; the model may have dropped them",
+                pages.join(", ")
+            ));
+        }
     }
 
     pub fn print_error(&self, error: &str) {
-        println!(
+        eprintln!(
             "\n{} {} {}",
             ERROR,
             style("ERROR OCCURRED:").red().bold().blink(),
             ERROR
         );
-        println!("{}", style("─".repeat(65)).red());
-        println!("{}", style(error).red());
-        println!("{}", style("─".repeat(65)).red());
+        eprintln!("{}", style("─".repeat(65)).red());
+        eprintln!("{}", style(error).red());
+        eprintln!("{}", style("─".repeat(65)).red());
+    }
+}
+
+impl ProgressSink for VerboseUI {
+    fn job_started(&self, path: &str, size: u64) {
+        self.print_banner();
+        self.print_file_info(path, size as usize);
+    }
+
+    fn page_count(&self, total_pages: usize) {
+        self.print_page_count(total_pages);
+    }
+
+    fn resume_status(&self, completed: usize, total: usize) {
+        self.print_resume_status(completed, total);
+    }
+
+    fn text_layer_checked(&self, local: usize, remote: usize) {
+        self.print_text_layer_status(local, remote);
+    }
+
+    fn upload_started(&self, total_bytes: u64) {
+        self.print_upload_start(total_bytes);
+        *self.upload_bar.lock().unwrap() = Some(self.create_upload_progress(total_bytes));
+    }
+
+    fn upload_progress(&self, sent: u64) {
+        if let Some(bar) = self.upload_bar.lock().unwrap().as_ref() {
+            bar.set_position(sent);
+        }
+    }
+
+    fn upload_completed(&self, file: &UploadedFile) {
+        self.print_upload_complete(file);
+    }
+
+    fn upload_reused(&self, file: &UploadedFile) {
+        self.print_upload_reused(&file.name, &file.uri);
+    }
+
+    fn uploads_finished(&self) {
+        if let Some(bar) = self.upload_bar.lock().unwrap().take() {
+            bar.finish();
+        }
+    }
+
+    fn processing_started(
+        &self,
+        ranges: &[PageRange],
+        pages_per_chunk: usize,
+        model: &str,
+        rate_limits: &RateLimits,
+    ) {
+        self.print_processing_start(ranges.len(), pages_per_chunk, model, rate_limits);
+        let mut bars = self.range_bars.lock().unwrap();
+        for range in ranges {
+            bars.insert(*range, self.create_chunk_progress(range.start, range.end));
+        }
+    }
+
+    fn range_started(&self, range: PageRange) {
+        if let Some(bar) = self.range_bars.lock().unwrap().get(&range) {
+            self.update_chunk_progress(bar, "processing", 50);
+        }
+    }
+
+    fn retrying(
+        &self,
+        what: &str,
+        range: Option<PageRange>,
+        attempt: u32,
+        delay: Duration,
+        error: &Error,
+    ) {
+        if let Some(bar) =
+            range.and_then(|range| self.range_bars.lock().unwrap().get(&range).cloned())
+        {
+            self.update_chunk_progress(&bar, "rate_limited", 70);
+        }
+        self.print_retry_warning(what, attempt, delay, &error.to_string());
+    }
+
+    fn range_completed(&self, range: PageRange, chars: usize) {
+        if let Some(bar) = self.range_bars.lock().unwrap().get(&range) {
+            self.update_chunk_progress(bar, "completed", 100);
+        }
+        if chars > 0 {
+            self.print_extraction_stats(range, chars);
+        }
+    }
+
+    fn range_failed(&self, range: PageRange, error: &Error) {
+        if let Some(bar) = self.range_bars.lock().unwrap().get(&range) {
+            self.update_chunk_progress(bar, "failed", 100);
+        }
+        self.print_error(&format!("Failed pages {range}: {error}"));
+    }
+
+    fn warning(&self, message: &str) {
+        self.print_warning(message);
+    }
+
+    fn job_finished(&self, summary: &JobSummary) {
+        self.print_final_summary(
+            summary.ranges,
+            summary.succeeded,
+            &summary.failed,
+            summary.total_chars,
+            &summary.reversed,
+        );
+    }
+
+    fn pages_verified(&self, pages: &[PageVerification]) {
+        self.print_verification_report(pages);
+    }
+
+    fn harakat_counted(&self, counts: &[HarakatCount]) {
+        self.print_harakat_report(counts);
+    }
+
+    fn uploads_deleted(&self, deleted: usize) {
+        self.print_cleanup_summary(deleted);
+    }
+
+    fn resume_hint(&self, state_path: &Path) {
+        self.print_resume_hint(state_path);
+    }
+
+    fn output_written(&self, path: Option<&str>, elapsed: Duration) {
+        if let Some(path) = path {
+            eprintln!(
+                "\n{} {}",
+                style("💾").cyan(),
+                style(format!("Output saved to: {path}")).green().bold()
+            );
+        }
+        eprintln!(
+            "\n{} {}",
+            style("⏱️").cyan(),
+            style(format!(
+                "Total processing time: {:.2} seconds",
+                elapsed.as_secs_f64()
+            ))
+            .cyan()
+        );
+        eprintln!(
+            "\n{} {}",
+            style("🎯").green(),
+            style("Mission accomplished! Have a great day! 🌟")
+                .green()
+                .bold()
+        );
     }
 }
 
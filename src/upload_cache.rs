@@ -0,0 +1,107 @@
+use crate::error::{Error, Result};
+use crate::gemini_client::UploadedFile;
+use crate::job::UploadRecord;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of the cache inside the state directory.
+pub const CACHE_FILE: &str = "uploads.json";
+
+/// Remote files keyed by the SHA-256 of the uploaded bytes, so that
+/// identical PDFs (or identical page-range sub-PDFs) are uploaded once and
+/// reused by later runs until Gemini expires them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UploadCache {
+    pub entries: BTreeMap<String, UploadRecord>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl UploadCache {
+    /// Loads the cache stored in `state_dir`, starting empty when there is
+    /// none yet.
+    pub fn load(state_dir: &Path) -> Result<Self> {
+        let path = state_dir.join(CACHE_FILE);
+        let mut cache = if path.exists() {
+            let json =
+                fs::read_to_string(&path).map_err(Error::io("Failed to read upload cache"))?;
+            serde_json::from_str::<Self>(&json)
+                .map_err(|e| Error::parse("Failed to parse upload cache", e))?
+        } else {
+            Self::default()
+        };
+        cache.path = path;
+        Ok(cache)
+    }
+
+    /// Returns the cached upload of content with this hash if it has not
+    /// expired yet. Whether it still exists remotely is up to the caller.
+    pub fn get(&self, sha256: &str) -> Option<&UploadRecord> {
+        self.entries
+            .get(sha256)
+            .filter(|record| record.is_usable(Utc::now()))
+    }
+
+    pub fn insert(&mut self, sha256: &str, file: &UploadedFile) {
+        self.entries.insert(sha256.to_string(), file.into());
+    }
+
+    pub fn remove(&mut self, sha256: &str) {
+        self.entries.remove(sha256);
+    }
+
+    /// Whether a remote file is tracked by the cache.
+    pub fn contains_file(&self, name: &str) -> bool {
+        self.entries.values().any(|record| record.name == name)
+    }
+
+    /// Drops expired entries and writes the cache atomically.
+    pub fn save(&mut self) -> Result<()> {
+        let now = Utc::now();
+        self.entries.retain(|_, record| record.is_usable(now));
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(Error::io("Failed to create state directory"))?;
+        }
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| Error::parse("Failed to serialize upload cache", e))?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json).map_err(Error::io("Failed to write upload cache"))?;
+        fs::rename(&tmp_path, &self.path).map_err(Error::io("Failed to write upload cache"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    fn uploaded(name: &str, expires_in_hours: i64) -> UploadedFile {
+        UploadedFile {
+            name: name.to_string(),
+            uri: format!("https://file-uri/{name}"),
+            expiration_time: Some(Utc::now() + Duration::hours(expires_in_hours)),
+            ..UploadedFile::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trip_drops_expired_entries() {
+        let dir = TempDir::new().unwrap();
+        let mut cache = UploadCache::load(dir.path()).unwrap();
+        cache.insert("fresh", &uploaded("files/1", 24));
+        cache.insert("stale", &uploaded("files/2", -1));
+        cache.save().unwrap();
+
+        let loaded = UploadCache::load(dir.path()).unwrap();
+        assert_eq!(loaded.get("fresh").unwrap().name, "files/1");
+        assert!(loaded.get("stale").is_none());
+        assert!(loaded.contains_file("files/1"));
+        assert!(!loaded.contains_file("files/2"));
+    }
+}
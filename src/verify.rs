@@ -0,0 +1,111 @@
+use crate::bidi;
+use crate::normalize;
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// Character error rate above which a page is flagged by default.
+pub const DEFAULT_THRESHOLD: f64 = 0.1;
+
+/// How far the extracted text of a page is from the page's own text layer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageVerification {
+    pub page: usize,
+    /// Character error rate: edits needed to turn the text layer into the
+    /// extracted text, per character of the text layer.
+    pub cer: f64,
+    /// Word error rate, the same per word.
+    pub wer: f64,
+    /// Whether the character error rate exceeds the threshold.
+    pub flagged: bool,
+}
+
+/// Compares the text extracted for `page` with its text layer.
+///
+/// Both texts are compared without markup, punctuation, diacritics,
+/// tatweel and alef variants, and a text layer in visual order is put into
+/// logical order first, so that only missing, extra or misread words count.
+pub fn compare(page: usize, extracted: &str, text_layer: &str, threshold: f64) -> PageVerification {
+    let extracted = comparable(extracted);
+    let reference = comparable(&bidi::repair(text_layer));
+
+    let cer = error_rate(
+        &extracted.chars().collect::<Vec<_>>(),
+        &reference.chars().collect::<Vec<_>>(),
+    );
+    let wer = error_rate(
+        &extracted.split(' ').collect::<Vec<_>>(),
+        &reference.split(' ').collect::<Vec<_>>(),
+    );
+    PageVerification {
+        page,
+        cer,
+        wer,
+        flagged: cer > threshold,
+    }
+}
+
+/// Letters and digits of `text`, with words separated by single spaces.
+fn comparable(text: &str) -> String {
+    let folded: String = text
+        .nfkc()
+        .filter(|&c| !normalize::is_diacritic(c) && c != '\u{0640}')
+        .map(|c| match c {
+            'أ' | 'إ' | 'آ' | 'ٱ' => 'ا',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Levenshtein distance between `hypothesis` and `reference`, divided by
+/// the length of `reference`.
+pub fn error_rate<T: PartialEq>(hypothesis: &[T], reference: &[T]) -> f64 {
+    if reference.is_empty() {
+        return if hypothesis.is_empty() { 0.0 } else { 1.0 };
+    }
+
+    let mut previous: Vec<usize> = (0..=hypothesis.len()).collect();
+    let mut current = vec![0; hypothesis.len() + 1];
+    for (i, expected) in reference.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, found) in hypothesis.iter().enumerate() {
+            let substitution = previous[j] + usize::from(expected != found);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[hypothesis.len()] as f64 / reference.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_rate() {
+        let reference: Vec<char> = "kitten".chars().collect();
+        let hypothesis: Vec<char> = "sitting".chars().collect();
+        assert_eq!(error_rate(&hypothesis, &reference), 3.0 / 6.0);
+        assert_eq!(error_rate(&reference, &reference), 0.0);
+        assert_eq!(error_rate::<char>(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn test_compare_ignores_markup_and_diacritics() {
+        let layer = "ذهب الولد إلى المدرسة، ثم عاد إلى البيت.";
+        let extracted = "## ذَهَبَ الولد الى المدرسة\n\nثم عاد إلى البيت";
+        let verification = compare(3, extracted, layer, DEFAULT_THRESHOLD);
+        assert_eq!(verification.cer, 0.0);
+        assert_eq!(verification.wer, 0.0);
+        assert!(!verification.flagged);
+    }
+
+    #[test]
+    fn test_compare_flags_skipped_text() {
+        let layer = "ذهب الولد إلى المدرسة ثم عاد إلى البيت وتناول الغداء مع أسرته";
+        let verification = compare(1, "ذهب الولد إلى المدرسة", layer, DEFAULT_THRESHOLD);
+        assert!(verification.flagged);
+        assert!(verification.wer > 0.5);
+    }
+}